@@ -0,0 +1,288 @@
+use std::borrow::Cow;
+use std::sync::Arc;
+
+use crate::{sequence, Dialect, Expression, List, Part, Sequence};
+
+/// Builds an [`Expression`] part by part, without having to escape anything.
+///
+/// The result is the same expression the parser would produce from its
+/// displayed form.
+///
+/// ```
+/// use bexpand::Expression;
+///
+/// let expression = Expression::builder()
+///     .plain("host-")
+///     .list(["a,b", "c"])
+///     .int_range(1, 10)
+///     .width(3)
+///     .build();
+/// assert_eq!(expression.to_string(), r"host-{a\,b,c}{=1..010}");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ExpressionBuilder<'a> {
+    parts: Vec<Part<'a>>,
+    dialect: Option<Arc<Dialect>>,
+}
+
+impl<'a> ExpressionBuilder<'a> {
+    /// Start an empty expression, which is the same as
+    /// [`Expression::builder`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a plain string, which is taken literally.
+    pub fn plain(mut self, plain: impl Into<Cow<'a, str>>) -> Self {
+        let plain = plain.into();
+        if plain.is_empty() {
+            return self;
+        }
+        // The parser never produces adjacent plain parts.
+        match self.parts.last_mut() {
+            Some(Part::Plain(last)) => last.to_mut().push_str(&plain),
+            _ => self.parts.push(Part::Plain(plain)),
+        }
+        self
+    }
+
+    /// Append a list of plain strings, which are each taken literally.
+    pub fn list<I, S>(self, items: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<Cow<'a, str>>,
+    {
+        self.list_of(
+            items
+                .into_iter()
+                .map(|item| ExpressionBuilder::new().plain(item).build()),
+        )
+    }
+
+    /// Append a list of expressions.
+    pub fn list_of<I>(mut self, items: I) -> Self
+    where
+        I: IntoIterator<Item = Expression<'a>>,
+    {
+        let items = items
+            .into_iter()
+            .map(|item| {
+                if item.parts.is_empty() {
+                    Part::Plain(Cow::Borrowed(""))
+                } else {
+                    Part::Expression(item)
+                }
+            })
+            .collect::<Vec<_>>();
+        let items = if items.is_empty() {
+            // An empty list is still parsed with a single empty item.
+            vec![Part::Plain(Cow::Borrowed(""))]
+        } else {
+            items
+        };
+        self.parts.push(Part::List(List(items)));
+        self
+    }
+
+    /// Append a numeric sequence from `start` to `end`, inclusive.
+    pub fn int_range(mut self, start: i64, end: i64) -> Self {
+        self.parts.push(Part::Sequence(Sequence::Int {
+            width: None,
+            sequence: sequence::Sequence {
+                start,
+                end,
+                incr: 1,
+            },
+        }));
+        self
+    }
+
+    /// Append a character sequence from `start` to `end`, inclusive.
+    pub fn char_range(mut self, start: char, end: char) -> Self {
+        self.parts.push(Part::Sequence(Sequence::Char {
+            sequence: sequence::Sequence {
+                start,
+                end,
+                incr: 1,
+            },
+            span: None,
+        }));
+        self
+    }
+
+    /// Set the stride of the last sequence.  A zero stride is normalized to
+    /// `1`, and a character stride saturates at `u32::MAX`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last part isn't a sequence.
+    pub fn stride(mut self, stride: u64) -> Self {
+        let stride = stride.max(1);
+        match self.parts.last_mut() {
+            Some(Part::Sequence(Sequence::Int { sequence, .. })) => sequence.incr = stride,
+            Some(Part::Sequence(Sequence::Char { sequence, .. })) => {
+                sequence.incr = u32::try_from(stride).unwrap_or(u32::MAX);
+            }
+            _ => panic!("stride must follow a sequence"),
+        }
+        self
+    }
+
+    /// Zero-pad each number of the last numeric sequence to the given width.
+    /// As with the `=` specifier, numbers are always padded to at least the
+    /// width of the wider endpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the last part isn't a numeric sequence.
+    pub fn width(mut self, width: usize) -> Self {
+        match self.parts.last_mut() {
+            Some(Part::Sequence(Sequence::Int {
+                width: current,
+                sequence,
+            })) => {
+                let natural = sequence
+                    .start
+                    .to_string()
+                    .len()
+                    .max(sequence.end.to_string().len());
+                *current = Some(width.max(natural));
+            }
+            _ => panic!("width must follow a numeric sequence"),
+        }
+        self
+    }
+
+    /// Append all the parts of another expression.
+    pub fn expression(mut self, expression: Expression<'a>) -> Self {
+        for part in expression.parts {
+            self = match part {
+                Part::Plain(plain) => self.plain(plain),
+                part => {
+                    self.parts.push(part);
+                    self
+                }
+            };
+        }
+        self
+    }
+
+    /// Set the dialect the expression is displayed with.
+    pub fn dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = (dialect != Dialect::bexpand()).then(|| Arc::new(dialect));
+        self
+    }
+
+    /// Finish the expression.
+    ///
+    /// In the Bash dialect, a list of a single item is displayed as just that
+    /// item, as Bash takes braces without a separator literally.  An empty
+    /// list has no Bash form at all, and is displayed as `{}`, which Bash
+    /// takes literally too.
+    pub fn build(self) -> Expression<'a> {
+        Expression {
+            parts: self.parts,
+            dialect: self.dialect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(expression: Expression<'_>) -> Vec<String> {
+        expression
+            .into_iter()
+            .map(|s| s.unwrap().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_builder_round_trip() {
+        let test_cases = [
+            (
+                Expression::builder()
+                    .plain("a{")
+                    .plain("b")
+                    .list(["", "c,d", r"e\f"])
+                    .build(),
+                r"a\{b{,c\,d,e\\f}",
+            ),
+            (
+                Expression::builder()
+                    .int_range(-1, 1000)
+                    .stride(300)
+                    .width(2)
+                    .char_range('.', '{')
+                    .stride(0)
+                    .build(),
+                r"{=-1..1000..300}{\...\{}",
+            ),
+            (
+                Expression::builder().int_range(-5, 5).width(4).build(),
+                "{=-5..0005}",
+            ),
+            (
+                Expression::builder()
+                    .list_of([
+                        Expression::builder().char_range('1', '3').build(),
+                        Expression::builder().build(),
+                        Expression::builder().plain("x").list(["y"]).build(),
+                    ])
+                    .list(Vec::<&str>::new())
+                    .build(),
+                r"{{\1..3},,x{y}}{}",
+            ),
+            (
+                Expression::builder()
+                    .plain("x")
+                    .list(["1..5"])
+                    .list(["a..c"])
+                    .list(["1..5", "a..c"])
+                    .build(),
+                r"x{1\..5}{a\..c}{1..5,a..c}",
+            ),
+        ];
+        for (expression, source) in test_cases {
+            assert_eq!(expression.to_string(), source);
+            let parsed = Expression::try_from(source).unwrap();
+            assert_eq!(expand(expression), expand(parsed), "{source}");
+        }
+    }
+
+    #[test]
+    fn test_builder_dialect() {
+        let dialect = Dialect::builder()
+            .delimiters('[', ']')
+            .separator('|')
+            .build()
+            .unwrap();
+        let expression = Expression::builder()
+            .plain("[x]")
+            .list(["a|b", "c"])
+            .dialect(dialect.clone())
+            .build();
+        let source = expression.to_string();
+        assert_eq!(source, r"\[x\][a\|b|c]");
+        let parsed = Expression::parse_with(&source, &dialect).unwrap();
+        assert_eq!(expand(expression), expand(parsed));
+    }
+
+    #[test]
+    fn test_builder_bash_single_item() {
+        let expression = Expression::builder()
+            .plain("x")
+            .list(["y,"])
+            .list(["a", "b"])
+            .list_of([Expression::builder()
+                .list_of([Expression::builder().int_range(1, 2).build()])
+                .build()])
+            .dialect(Dialect::bash())
+            .build();
+        let source = expression.to_string();
+        assert_eq!(source, r"xy\,{a,b}{1..2}");
+        let parsed = Expression::parse_with(&source, &Dialect::bash()).unwrap();
+        assert_eq!(expand(expression), expand(parsed));
+    }
+}
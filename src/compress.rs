@@ -0,0 +1,358 @@
+use crate::Expression;
+
+/// Runs of numbers or chars need at least this many strings to be worth a
+/// sequence.
+pub(crate) const MIN_RUN: usize = 3;
+
+/// Synthesize a compact expression whose expansion is exactly `strings`, in
+/// the same order.  This is the inverse of expansion, for printing large
+/// inventories compactly.
+///
+/// Common prefixes and suffixes are factored out, runs of numbers or chars
+/// become sequences, with a stride and zero-padding as needed, and strings
+/// that are a cartesian product of others become a product of lists.
+///
+/// ```
+/// let hosts = ["web01.us", "web01.eu", "web02.us", "web02.eu", "web03.us", "web03.eu"];
+/// let expression = bexpand::compress(hosts);
+/// assert_eq!(expression.to_string(), "web{=1..03}.{us,eu}");
+/// let expanded: Vec<_> = expression.into_iter().map(Result::unwrap).collect();
+/// assert_eq!(expanded, hosts);
+/// ```
+pub fn compress<I, S>(strings: I) -> Expression<'static>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    let strings: Vec<String> = strings
+        .into_iter()
+        .map(|s| s.as_ref().to_string())
+        .collect();
+    match &strings[..] {
+        // A lone empty string needs an empty list, as an expression without
+        // parts has no outputs at all.
+        [string] if string.is_empty() => Expression::builder().list([""]).build(),
+        strings => expression(strings),
+    }
+}
+
+fn expression(strings: &[String]) -> Expression<'static> {
+    match strings {
+        [] => return Expression::builder().build(),
+        [string] => return Expression::builder().plain(string.clone()).build(),
+        _ => {}
+    }
+    let (prefix, suffix) = affixes(strings);
+    let middles: Vec<String> = strings
+        .iter()
+        .map(|s| s[prefix.len()..s.len() - suffix.len()].to_string())
+        .collect();
+    let builder = Expression::builder().plain(prefix);
+    let builder = if let Some(run) = run(&middles) {
+        builder.expression(run)
+    } else if let Some((outer, inner)) = product(&middles) {
+        builder
+            .expression(expression(&outer))
+            .expression(expression(&inner))
+    } else {
+        builder.list_of(segments(&middles).into_iter().map(expression))
+    };
+    builder.plain(suffix).build()
+}
+
+fn is_digit_start(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn is_digit_end(s: &str) -> bool {
+    s.ends_with(|c: char| c.is_ascii_digit())
+}
+
+/// The common prefix and suffix of `strings`, which don't overlap.  Neither
+/// splits a run of digits, so that numbers stay whole.
+fn affixes(strings: &[String]) -> (String, String) {
+    let first = &strings[0];
+    let shortest = strings.iter().map(String::len).min().unwrap_or(0);
+    let mut prefix = first
+        .char_indices()
+        .map(|(i, c)| i + c.len_utf8())
+        .take_while(|&end| strings.iter().all(|s| s.get(..end) == first.get(..end)))
+        .last()
+        .unwrap_or(0);
+    let mut suffix = first
+        .char_indices()
+        .rev()
+        .map(|(i, _)| first.len() - i)
+        .take_while(|&len| {
+            len <= shortest - prefix
+                && strings
+                    .iter()
+                    .all(|s| s.get(s.len() - len..) == first.get(first.len() - len..))
+        })
+        .last()
+        .unwrap_or(0);
+    if is_digit_end(&first[..prefix])
+        && strings
+            .iter()
+            .any(|s| is_digit_start(&s[prefix..s.len() - suffix]))
+    {
+        prefix = first[..prefix]
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .len();
+    }
+    if is_digit_start(&first[first.len() - suffix..])
+        && strings
+            .iter()
+            .any(|s| is_digit_end(&s[prefix..s.len() - suffix]))
+    {
+        suffix = first[first.len() - suffix..]
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .len();
+    }
+    (
+        first[..prefix].to_string(),
+        first[first.len() - suffix..].to_string(),
+    )
+}
+
+/// A sequence that produces exactly `strings`, if they are numbers or chars
+/// with a constant step.
+fn run(strings: &[String]) -> Option<Expression<'static>> {
+    if strings.len() < MIN_RUN {
+        return None;
+    }
+    let builder = if let Some(numbers) = strings
+        .iter()
+        .map(|s| s.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()
+    {
+        let (first, last) = (numbers[0], numbers[numbers.len() - 1]);
+        let step = numbers[1].checked_sub(first)?;
+        if step == 0
+            || numbers
+                .windows(2)
+                .any(|pair| pair[1].checked_sub(pair[0]) != Some(step))
+        {
+            return None;
+        }
+        // Numbers are either all written plainly, or all zero-padded to the
+        // same width.
+        let len = strings[0].len();
+        let builder = Expression::builder()
+            .int_range(first, last)
+            .stride(step.unsigned_abs());
+        if strings
+            .iter()
+            .zip(&numbers)
+            .all(|(s, n)| *s == n.to_string())
+        {
+            builder
+        } else if strings
+            .iter()
+            .zip(&numbers)
+            .all(|(s, n)| *s == format!("{n:0len$}"))
+        {
+            builder.width(len)
+        } else {
+            return None;
+        }
+    } else {
+        let chars = strings
+            .iter()
+            .map(|s| {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(u32::from(c)),
+                    _ => None,
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let step = i64::from(chars[1]) - i64::from(chars[0]);
+        if step == 0
+            || chars
+                .windows(2)
+                .any(|pair| i64::from(pair[1]) - i64::from(pair[0]) != step)
+        {
+            return None;
+        }
+        Expression::builder()
+            .char_range(
+                char::from_u32(chars[0])?,
+                char::from_u32(chars[chars.len() - 1])?,
+            )
+            .stride(step.unsigned_abs())
+    };
+    // Not every run can be written, like zero-padded numbers narrower than
+    // the widest of them.
+    let run = builder.build();
+    run.iter()
+        .map(Result::ok)
+        .eq(strings.iter().map(|s| Some(s.as_str().into())))
+        .then_some(run)
+}
+
+/// Split `strings` into an outer and an inner list whose cartesian product
+/// they are, in order, if they are one.
+fn product(strings: &[String]) -> Option<(Vec<String>, Vec<String>)> {
+    let n = strings.len();
+    (2..=n / 2).filter(|&k| n.is_multiple_of(k)).find_map(|k| {
+        let mut outer = Vec::with_capacity(n / k);
+        let mut inner: Option<Vec<&str>> = None;
+        for block in strings.chunks(k) {
+            let first = &block[0];
+            let prefix = first
+                .char_indices()
+                .map(|(i, c)| i + c.len_utf8())
+                .take_while(|&end| block.iter().all(|s| s.get(..end) == first.get(..end)))
+                .last()
+                .unwrap_or(0);
+            let rest: Vec<&str> = block.iter().map(|s| &s[prefix..]).collect();
+            match &inner {
+                Some(inner) if *inner != rest => return None,
+                Some(_) => {}
+                None => inner = Some(rest),
+            }
+            outer.push(first[..prefix].to_string());
+        }
+        let inner = inner?.into_iter().map(String::from).collect();
+        Some((outer, inner))
+    })
+}
+
+/// Split `strings` into consecutive segments that compress well on their
+/// own: runs, and otherwise strings that start the same way.
+fn segments(strings: &[String]) -> Vec<&[String]> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    while start < strings.len() {
+        let rest = &strings[start..];
+        let mut len = run_len(rest);
+        if len == 1 {
+            if let Some(c) = rest[0].chars().next() {
+                len = rest.iter().take_while(|s| s.starts_with(c)).count();
+            }
+        }
+        if len == strings.len() {
+            // Nothing is gained by a single segment.
+            len = 1;
+        }
+        segments.push(&rest[..len]);
+        start += len;
+    }
+    segments
+}
+
+/// The length of the longest run at the start of `strings`, or 1 if there is
+/// none.
+fn run_len(strings: &[String]) -> usize {
+    let is_run = |len: usize| {
+        let (prefix, suffix) = affixes(&strings[..len]);
+        let middles: Vec<String> = strings[..len]
+            .iter()
+            .map(|s| s[prefix.len()..s.len() - suffix.len()].to_string())
+            .collect();
+        run(&middles).is_some()
+    };
+    if strings.len() < MIN_RUN {
+        return 1;
+    }
+    // Extend the run while the step between the first two values holds, so
+    // that each string is only looked at once, and only then check that the
+    // run can be written.
+    let (prefix, suffix) = affixes(&strings[..MIN_RUN]);
+    let value = |s: &String| {
+        let middle = s.strip_prefix(&prefix)?.strip_suffix(&suffix)?;
+        if let Ok(number) = middle.parse::<i64>() {
+            return Some(number);
+        }
+        let mut chars = middle.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(i64::from(u32::from(c))),
+            _ => None,
+        }
+    };
+    let (Some(first), Some(mut last)) = (value(&strings[0]), value(&strings[1])) else {
+        return 1;
+    };
+    let step = match last.checked_sub(first) {
+        Some(step) if step != 0 => step,
+        _ => return 1,
+    };
+    let mut len = 2;
+    while let Some(next) = strings.get(len).and_then(value) {
+        if next.checked_sub(last) != Some(step) {
+            break;
+        }
+        last = next;
+        len += 1;
+    }
+    if len < MIN_RUN {
+        return 1;
+    }
+    if is_run(len) {
+        return len;
+    }
+    // The run can't be written as a whole, like zero-padded numbers that
+    // gain a digit, so find the longest part of it that can.
+    let (mut low, mut high) = (MIN_RUN - 1, len);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if is_run(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    if low < MIN_RUN {
+        1
+    } else {
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(strings: &[&str]) -> String {
+        let expression = compress(strings);
+        let expanded: Vec<_> = expression.iter().map(Result::unwrap).collect();
+        assert_eq!(expanded, strings, "{expression}");
+        expression.to_string()
+    }
+
+    #[test]
+    fn test_compress() {
+        let test_cases: [(&[&str], &str); 10] = [
+            (&[], ""),
+            (&["a"], "a"),
+            (&["a1", "a2", "a3", "a4"], "a{1..4}"),
+            (&["x08", "x10", "x12"], "x{=8..12..2}"),
+            (&["10", "20", "30"], "{10..30..10}"),
+            (&["c", "b", "a"], "{c..a}"),
+            (&["a.x", "a.y", "b.x", "b.y"], "{a,b}.{x,y}"),
+            (&["a", "a", "a"], "a{,,}"),
+            (&["ab", "a", "b1", "b2", "b3", "c"], "{a{b,},b{1..3},c}"),
+            (
+                &["apple", "apricot", "banana", "blueberry"],
+                "{ap{ple,ricot},b{anana,lueberry}}",
+            ),
+        ];
+        for (strings, expected) in test_cases {
+            assert_eq!(round_trip(strings), expected);
+        }
+    }
+
+    #[test]
+    fn test_compress_long_runs() {
+        let hosts: Vec<_> = (0..8000)
+            .filter(|&i| i != 4321)
+            .map(|i| format!("web{i:05}.us"))
+            .collect();
+        let hosts: Vec<_> = hosts.iter().map(String::as_str).collect();
+        assert_eq!(round_trip(&hosts), "web{{=0..04320},{=4322..07999}}.us",);
+        let strings: Vec<_> = (95..105).map(|i| format!("x{i:03}")).collect();
+        let strings: Vec<_> = strings.iter().map(String::as_str).collect();
+        assert_eq!(round_trip(&strings), "x{=95..104}");
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{CursorError, Expression, Part, Sequence};
+
+/// The position of an [`ExpressionIterator`](crate::ExpressionIterator) within
+/// its expression's outputs, so that a long expansion can be resumed with
+/// [`Expression::iter_from`].
+///
+/// A cursor carries a fingerprint of the expression it came from, so it can't
+/// be applied to a different expression by mistake.  Its Display form, like
+/// `8c0e8f7d8a1b2c3d:1200:0`, parses back with `FromStr`, so it can be saved
+/// anywhere a string can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cursor {
+    pub(crate) fingerprint: u64,
+    pub(crate) front: u128,
+    pub(crate) back: u128,
+}
+
+impl Cursor {
+    /// The fingerprint of the expression this cursor belongs to.
+    pub fn fingerprint(&self) -> u64 {
+        self.fingerprint
+    }
+
+    /// The index of the next output.
+    pub fn index(&self) -> u128 {
+        self.front
+    }
+
+    /// The number of outputs already taken from the back.
+    pub fn back(&self) -> u128 {
+        self.back
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}:{}:{}", self.fingerprint, self.front, self.back)
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = CursorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || CursorError::Invalid(s.to_owned());
+        let mut fields = s.split(':');
+        let mut field = || fields.next().ok_or_else(invalid);
+        let fingerprint = u64::from_str_radix(field()?, 16).map_err(|_| invalid())?;
+        let front = field()?.parse().map_err(|_| invalid())?;
+        let back = field()?.parse().map_err(|_| invalid())?;
+        if fields.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Cursor {
+            fingerprint,
+            front,
+            back,
+        })
+    }
+}
+
+/// A 64-bit FNV-1a hasher, which unlike the standard library's hashers is
+/// guaranteed to stay the same between releases.
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    /// Write a length, so that adjacent fields can't run into each other.
+    fn write_len(&mut self, len: usize) {
+        self.write(&(len as u64).to_le_bytes());
+    }
+
+    fn expression(&mut self, expression: &Expression<'_>) {
+        self.write_len(expression.parts.len());
+        for part in &expression.parts {
+            self.part(part);
+        }
+    }
+
+    fn part(&mut self, part: &Part<'_>) {
+        match part {
+            Part::Plain(plain) => {
+                self.write(&[0]);
+                self.write_len(plain.len());
+                self.write(plain.as_bytes());
+            }
+            Part::List(list) => {
+                self.write(&[1]);
+                self.write_len(list.items().len());
+                for item in list.items() {
+                    self.part(item);
+                }
+            }
+            Part::Sequence(Sequence::Int { width, sequence }) => {
+                self.write(&[2]);
+                match width {
+                    Some(width) => {
+                        self.write(&[1]);
+                        self.write_len(*width);
+                    }
+                    None => self.write(&[0]),
+                }
+                self.write(&sequence.start.to_le_bytes());
+                self.write(&sequence.end.to_le_bytes());
+                self.write(&sequence.incr.to_le_bytes());
+            }
+            Part::Sequence(Sequence::Char { sequence, .. }) => {
+                self.write(&[3]);
+                self.write(&u32::from(sequence.start).to_le_bytes());
+                self.write(&u32::from(sequence.end).to_le_bytes());
+                self.write(&sequence.incr.to_le_bytes());
+            }
+            Part::Expression(expression) => {
+                self.write(&[4]);
+                self.expression(expression);
+            }
+        }
+    }
+}
+
+/// Hash everything in the expression that determines its outputs.  The
+/// dialect and spans only affect how it's displayed and reported, so they're
+/// left out.
+pub(crate) fn fingerprint(expression: &Expression<'_>) -> u64 {
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    hasher.expression(expression);
+    hasher.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = Cursor {
+            fingerprint: 0x0123_4567_89ab_cdef,
+            front: 1200,
+            back: 3,
+        };
+        assert_eq!(cursor.to_string(), "0123456789abcdef:1200:3");
+        assert_eq!(cursor.to_string().parse(), Ok(cursor));
+        for invalid in ["", "0123456789abcdef:1200", "xyz:1:2", "1:2:3:4", "1:-2:3"] {
+            assert_eq!(
+                invalid.parse::<Cursor>(),
+                Err(CursorError::Invalid(invalid.to_owned()))
+            );
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::borrow::Cow;
+
+use crate::error::DialectError;
+
+/// The syntax used to parse and display expressions.
+///
+/// The default is bexpand's own syntax, as documented in the README.  The
+/// special characters may be changed with [`Dialect::builder`], so that
+/// `[a|b]`-style templates can be used, or `\` can be left as a plain
+/// character.
+///
+/// The Bash dialect instead follows Bash's rules as closely as possible:
+///
+/// * Unmatched braces are taken literally.
+/// * Braces without a comma, like `{a}` or `{}`, are taken literally, though
+///   anything inside them is still expanded.
+/// * `${...}` is taken literally.
+/// * Numeric sequences are zero-padded when either end has a leading zero, as
+///   in `{001..100}`, and the `=` specifier is not supported.
+/// * Sequence strides may be negative.  Only the magnitude is used, as the
+///   direction always comes from the start and end.
+/// * Character sequences may only use ASCII letters, as in `{a..Z}`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dialect {
+    open: char,
+    close: char,
+    separator: char,
+    range: Cow<'static, str>,
+    escape: char,
+    bash: bool,
+    strict_strides: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect::bexpand()
+    }
+}
+
+impl Dialect {
+    /// bexpand's own syntax.  This is the default.
+    pub const fn bexpand() -> Self {
+        Dialect {
+            open: '{',
+            close: '}',
+            separator: ',',
+            range: Cow::Borrowed(".."),
+            escape: '\\',
+            bash: false,
+            strict_strides: false,
+        }
+    }
+
+    /// Bash-compatible syntax.
+    pub const fn bash() -> Self {
+        Dialect {
+            open: '{',
+            close: '}',
+            separator: ',',
+            range: Cow::Borrowed(".."),
+            escape: '\\',
+            bash: true,
+            strict_strides: false,
+        }
+    }
+
+    /// A builder for a custom dialect, starting from bexpand's own syntax.
+    pub fn builder() -> DialectBuilder {
+        DialectBuilder {
+            dialect: Dialect::bexpand(),
+        }
+    }
+
+    /// The opening delimiter of lists and sequences.
+    pub fn open(&self) -> char {
+        self.open
+    }
+
+    /// The closing delimiter of lists and sequences.
+    pub fn close(&self) -> char {
+        self.close
+    }
+
+    /// The separator between alternatives of a list.
+    pub fn separator(&self) -> char {
+        self.separator
+    }
+
+    /// The operator between the start, end, and stride of a sequence.
+    pub fn range_operator(&self) -> &str {
+        &self.range
+    }
+
+    /// The character that makes the following character literal.
+    pub fn escape(&self) -> char {
+        self.escape
+    }
+
+    /// Whether this follows Bash's rules.
+    pub fn is_bash(&self) -> bool {
+        self.bash
+    }
+
+    /// Whether a sequence with a stride that isn't a number, like
+    /// `{1..5..x}`, is an error, rather than a list that is taken literally.
+    pub fn strict_strides(&self) -> bool {
+        self.strict_strides
+    }
+
+    /// Whether this character needs to be escaped in a plain string.
+    pub(crate) fn is_special(&self, c: char) -> bool {
+        c == self.open || c == self.close || c == self.separator || c == self.escape
+    }
+}
+
+/// Builds a [`Dialect`] with custom special characters.
+#[derive(Clone, Debug)]
+pub struct DialectBuilder {
+    dialect: Dialect,
+}
+
+impl DialectBuilder {
+    /// Whether to follow Bash's rules.
+    pub fn bash(mut self, bash: bool) -> Self {
+        self.dialect.bash = bash;
+        self
+    }
+
+    /// Set the opening and closing delimiters of lists and sequences.  The
+    /// default is `{` and `}`.
+    pub fn delimiters(mut self, open: char, close: char) -> Self {
+        self.dialect.open = open;
+        self.dialect.close = close;
+        self
+    }
+
+    /// Set the separator between alternatives of a list.  The default is `,`.
+    pub fn separator(mut self, separator: char) -> Self {
+        self.dialect.separator = separator;
+        self
+    }
+
+    /// Set the operator between the start, end, and stride of a sequence.  The
+    /// default is `..`.
+    pub fn range_operator(mut self, range: impl Into<Cow<'static, str>>) -> Self {
+        self.dialect.range = range.into();
+        self
+    }
+
+    /// Set the character that makes the following character literal.  The
+    /// default is `\`.
+    pub fn escape(mut self, escape: char) -> Self {
+        self.dialect.escape = escape;
+        self
+    }
+
+    /// Make a sequence with a stride that isn't a number, like `{1..5..x}`,
+    /// an [`InvalidStride`](crate::ParseErrorKind::InvalidStride) error,
+    /// rather than a list that is taken literally.  The default is `false`.
+    /// The Bash dialect always takes such braces literally, as Bash does.
+    pub fn strict_strides(mut self, strict: bool) -> Self {
+        self.dialect.strict_strides = strict;
+        self
+    }
+
+    /// Build the dialect, checking that the special characters don't conflict.
+    pub fn build(self) -> Result<Dialect, DialectError> {
+        let dialect = self.dialect;
+        let special = [
+            dialect.open,
+            dialect.close,
+            dialect.separator,
+            dialect.escape,
+        ];
+        for (i, c) in special.iter().enumerate() {
+            if special[i + 1..].contains(c) {
+                return Err(DialectError::DuplicateCharacter(*c));
+            }
+        }
+        if dialect.range.is_empty()
+            || dialect
+                .range
+                .chars()
+                .any(|c| dialect.is_special(c) || c.is_ascii_digit())
+        {
+            return Err(DialectError::InvalidRangeOperator(
+                dialect.range.into_owned(),
+            ));
+        }
+        Ok(dialect)
+    }
+}
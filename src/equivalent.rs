@@ -0,0 +1,421 @@
+//! Deciding whether two expressions produce the same outputs.
+//!
+//! Both expressions are first brought to the same form, simplified when
+//! comparing sequences and canonicalized when comparing sets, and the parts
+//! they begin and end with are peeled off.  Equal outputs of what is left
+//! mean equal outputs of the whole, so only the differing middles are
+//! enumerated, and any difference found there is checked against the whole
+//! expressions before it's reported.
+
+use std::borrow::Cow;
+
+use crate::{ExpansionError, Expression, List, Part, Sequence};
+
+/// At most this many outputs are enumerated from each expression.
+const ENUMERATE_LIMIT: u128 = 1 << 16;
+
+/// How [`Expression::equivalent`] compares the outputs of two expressions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Equivalence {
+    /// The same outputs in the same order, each as many times.
+    #[default]
+    Sequence,
+    /// The same outputs in any order, however many times each.
+    Set,
+}
+
+/// Whether two expressions are equivalent, as decided by
+/// [`Expression::equivalent`].
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Verdict {
+    /// The expressions produce the same outputs.
+    Equivalent,
+    /// The expressions differ, as shown by an output of one of them.  When
+    /// comparing sequences, the other produces a different output or none at
+    /// the same position, and when comparing sets, the other never produces
+    /// it.
+    Different(String),
+    /// The expressions have too many outputs to tell.
+    Unknown,
+}
+
+impl Verdict {
+    /// Whether the expressions are known to be equivalent.
+    pub fn is_equivalent(&self) -> bool {
+        matches!(self, Verdict::Equivalent)
+    }
+
+    /// The counterexample, if the expressions differ.
+    pub fn counterexample(&self) -> Option<&str> {
+        match self {
+            Verdict::Different(output) => Some(output),
+            _ => None,
+        }
+    }
+}
+
+type Output<'a> = Option<Result<Cow<'a, str>, ExpansionError>>;
+
+/// Whether two outputs at the same position differ.  Errors are only told
+/// apart from strings.
+fn differ(
+    a: Option<&Result<Cow<'_, str>, ExpansionError>>,
+    b: Option<&Result<Cow<'_, str>, ExpansionError>>,
+) -> bool {
+    match (a, b) {
+        (Some(Ok(a)), Some(Ok(b))) => a != b,
+        (Some(Err(_)), Some(Err(_))) | (None, None) => false,
+        _ => true,
+    }
+}
+
+/// The output that shows two outputs at the same position differ, if they do.
+fn witness(a: Output<'_>, b: Output<'_>) -> Option<String> {
+    if !differ(a.as_ref(), b.as_ref()) {
+        return None;
+    }
+    match (a, b) {
+        (Some(Ok(output)), _) | (_, Some(Ok(output))) => Some(output.into_owned()),
+        _ => None,
+    }
+}
+
+/// An expression of `parts`, which produces the empty string if there are
+/// none.
+fn expression(parts: Vec<Part<'_>>) -> Expression<'_> {
+    let parts = if parts.is_empty() {
+        vec![Part::List(List(vec![Part::Plain(Cow::Borrowed(""))]))]
+    } else {
+        parts
+    };
+    Expression {
+        parts,
+        dialect: None,
+    }
+}
+
+/// Whether two parts are the same, field by field.  Parts that display the
+/// same may still differ, like a sequence narrower than its endpoints, and
+/// spans are left out, as they don't change the outputs.
+fn same(a: &Part<'_>, b: &Part<'_>) -> bool {
+    match (a, b) {
+        (Part::Plain(a), Part::Plain(b)) => a == b,
+        (Part::List(a), Part::List(b)) => all_same(a.items(), b.items()),
+        (
+            Part::Sequence(Sequence::Int { width, sequence }),
+            Part::Sequence(Sequence::Int {
+                width: other_width,
+                sequence: other,
+            }),
+        ) => {
+            width == other_width
+                && sequence.start == other.start
+                && sequence.end == other.end
+                && sequence.incr == other.incr
+        }
+        (
+            Part::Sequence(Sequence::Char { sequence, .. }),
+            Part::Sequence(Sequence::Char {
+                sequence: other, ..
+            }),
+        ) => {
+            sequence.start == other.start
+                && sequence.end == other.end
+                && sequence.incr == other.incr
+        }
+        (Part::Expression(a), Part::Expression(b)) => all_same(a.parts(), b.parts()),
+        _ => false,
+    }
+}
+
+fn all_same(a: &[Part<'_>], b: &[Part<'_>]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+}
+
+/// Two expressions split into the parts they begin and end with, and the
+/// parts in between that differ.
+struct Peeled<'a> {
+    prefix: Vec<Part<'a>>,
+    a: Vec<Part<'a>>,
+    b: Vec<Part<'a>>,
+    suffix: Vec<Part<'a>>,
+}
+
+impl<'a> Peeled<'a> {
+    fn new(a: &[Part<'a>], b: &[Part<'a>]) -> Self {
+        let mut peeled = Peeled {
+            prefix: Vec::new(),
+            a: a.to_vec(),
+            b: b.to_vec(),
+            suffix: Vec::new(),
+        };
+        while let (Some(a), Some(b)) = (peeled.a.first(), peeled.b.first()) {
+            if same(a, b) {
+                peeled.prefix.push(peeled.a.remove(0));
+                peeled.b.remove(0);
+                continue;
+            }
+            let (Part::Plain(a), Part::Plain(b)) = (a, b) else {
+                break;
+            };
+            let len = a
+                .char_indices()
+                .map(|(i, c)| i + c.len_utf8())
+                .take_while(|&end| b.get(..end) == a.get(..end))
+                .last()
+                .unwrap_or(0);
+            if len == 0 {
+                break;
+            }
+            peeled
+                .prefix
+                .push(Part::Plain(Cow::Owned(a[..len].to_string())));
+            peeled.a[0] = Part::Plain(Cow::Owned(a[len..].to_string()));
+            peeled.b[0] = Part::Plain(Cow::Owned(b[len..].to_string()));
+            peeled
+                .a
+                .retain(|part| !matches!(part, Part::Plain(plain) if plain.is_empty()));
+            peeled
+                .b
+                .retain(|part| !matches!(part, Part::Plain(plain) if plain.is_empty()));
+        }
+        while let (Some(a), Some(b)) = (peeled.a.last(), peeled.b.last()) {
+            if same(a, b) {
+                peeled
+                    .suffix
+                    .insert(0, peeled.a.pop().expect("a part was compared"));
+                peeled.b.pop();
+                continue;
+            }
+            let (Part::Plain(a), Part::Plain(b)) = (a, b) else {
+                break;
+            };
+            let len = a
+                .char_indices()
+                .rev()
+                .map(|(i, _)| a.len() - i)
+                .take_while(|&len| {
+                    b.len() >= len && b.get(b.len() - len..) == a.get(a.len() - len..)
+                })
+                .last()
+                .unwrap_or(0);
+            if len == 0 {
+                break;
+            }
+            let (a_len, b_len) = (a.len() - len, b.len() - len);
+            peeled
+                .suffix
+                .insert(0, Part::Plain(Cow::Owned(a[a_len..].to_string())));
+            let last = peeled.a.len() - 1;
+            peeled.a[last] = Part::Plain(Cow::Owned(a[..a_len].to_string()));
+            let last = peeled.b.len() - 1;
+            peeled.b[last] = Part::Plain(Cow::Owned(b[..b_len].to_string()));
+            peeled
+                .a
+                .retain(|part| !matches!(part, Part::Plain(plain) if plain.is_empty()));
+            peeled
+                .b
+                .retain(|part| !matches!(part, Part::Plain(plain) if plain.is_empty()));
+        }
+        peeled
+    }
+
+    fn is_same(&self) -> bool {
+        all_same(&self.a, &self.b)
+    }
+}
+
+pub(crate) fn equivalent(a: &Expression<'_>, b: &Expression<'_>, mode: Equivalence) -> Verdict {
+    let (a, b) = match mode {
+        Equivalence::Sequence => (a.simplify(), b.simplify()),
+        Equivalence::Set => (a.canonicalize(), b.canonicalize()),
+    };
+    match (a.count(), b.count()) {
+        (Some(0), Some(0)) => return Verdict::Equivalent,
+        (Some(0), _) | (_, Some(0)) => {
+            let mut outputs = a.iter().chain(b.iter());
+            return match outputs.find_map(Result::ok) {
+                Some(output) => Verdict::Different(output.into_owned()),
+                None => Verdict::Unknown,
+            };
+        }
+        _ => {}
+    }
+    let peeled = Peeled::new(a.parts(), b.parts());
+    if peeled.is_same() {
+        return Verdict::Equivalent;
+    }
+    match mode {
+        Equivalence::Sequence => sequence(&a, &b, peeled),
+        Equivalence::Set => set(&a, &b, peeled),
+    }
+}
+
+fn sequence(a: &Expression<'_>, b: &Expression<'_>, peeled: Peeled<'_>) -> Verdict {
+    // The middles produce their outputs once for each output of the suffix,
+    // and the first outputs of the prefix and suffix are the same either way,
+    // so where the middles differ, the whole expressions differ too.
+    let suffix = expression(peeled.suffix).count();
+    let (middle_a, middle_b) = (expression(peeled.a), expression(peeled.b));
+    let (difference, complete) = match (middle_a.count(), middle_b.count()) {
+        (Some(x), Some(y)) if x == y => (
+            ends(&middle_a)
+                .zip(ends(&middle_b))
+                .take(ENUMERATE_LIMIT as usize)
+                .find(|((_, x), (_, y))| differ(Some(x), Some(y)))
+                .map(|((n, _), _)| n),
+            x <= ENUMERATE_LIMIT,
+        ),
+        _ => {
+            let (mut left, mut right) = (middle_a.iter(), middle_b.iter());
+            let difference = (0..ENUMERATE_LIMIT)
+                .map_while(|n| {
+                    let (x, y) = (left.next(), right.next());
+                    (x.is_some() || y.is_some()).then(|| (n, differ(x.as_ref(), y.as_ref())))
+                })
+                .find(|&(_, differ)| differ)
+                .map(|(n, _)| n);
+            (difference, false)
+        }
+    };
+    match difference {
+        Some(n) => {
+            let position = suffix.and_then(|suffix| n.checked_mul(suffix));
+            if let Some(output) =
+                position.and_then(|n| witness(a.nth_expansion(n), b.nth_expansion(n)))
+            {
+                return Verdict::Different(output);
+            }
+        }
+        None if complete => return Verdict::Equivalent,
+        None => {}
+    }
+    // Expressions with different numbers of outputs differ where the shorter
+    // one ends.
+    match (a.count(), b.count()) {
+        (Some(x), Some(y)) if x != y => {
+            let n = x.min(y);
+            match witness(a.nth_expansion(n), b.nth_expansion(n)) {
+                Some(output) => Verdict::Different(output),
+                None => Verdict::Unknown,
+            }
+        }
+        _ => Verdict::Unknown,
+    }
+}
+
+/// The outputs of an expression with their positions, alternately from the
+/// front and the back, as differences are often at either end.
+fn ends<'e>(
+    expression: &'e Expression<'_>,
+) -> impl Iterator<Item = (u128, Result<Cow<'e, str>, ExpansionError>)> + 'e {
+    let count = expression.count();
+    let mut iter = expression.iter();
+    let (mut front, mut back) = (0, 0);
+    std::iter::from_fn(move || match count {
+        Some(count) if back < front => {
+            let output = iter.next_back()?;
+            back += 1;
+            Some((count - back, output))
+        }
+        _ => {
+            let output = iter.next()?;
+            front += 1;
+            Some((front - 1, output))
+        }
+    })
+}
+
+/// The first output of an expression that `other` doesn't produce, among the
+/// outputs at either end, and whether every output was looked at.
+fn missing(expression: &Expression<'_>, other: &Expression<'_>) -> (Option<String>, bool) {
+    let complete = expression
+        .count()
+        .is_some_and(|count| count <= ENUMERATE_LIMIT);
+    let output = ends(expression)
+        .take(ENUMERATE_LIMIT as usize)
+        .filter_map(|(_, output)| output.ok())
+        .find(|output| !other.matches(output))
+        .map(Cow::into_owned);
+    (output, complete)
+}
+
+fn set(a: &Expression<'_>, b: &Expression<'_>, peeled: Peeled<'_>) -> Verdict {
+    fn first(parts: Vec<Part<'_>>) -> Option<String> {
+        expression(parts)
+            .iter()
+            .find_map(|output| output.ok().map(Cow::into_owned))
+    }
+    let unpeeled = peeled.prefix.is_empty() && peeled.suffix.is_empty();
+    let (prefix, suffix) = (first(peeled.prefix), first(peeled.suffix));
+    let (middle_a, middle_b) = (expression(peeled.a), expression(peeled.b));
+    let mut decided = true;
+    for (middle, other, whole) in [(&middle_a, &middle_b, b), (&middle_b, &middle_a, a)] {
+        match missing(middle, other) {
+            (Some(output), _) => {
+                // The output of the middle may still be produced with other
+                // outputs of the prefix and suffix.
+                if let (Some(prefix), Some(suffix)) = (&prefix, &suffix) {
+                    let output = format!("{prefix}{output}{suffix}");
+                    if !whole.matches(&output) {
+                        return Verdict::Different(output);
+                    }
+                }
+                decided = false;
+            }
+            (None, complete) => decided &= complete,
+        }
+    }
+    if decided {
+        return Verdict::Equivalent;
+    } else if unpeeled {
+        // The middles were the whole expressions.
+        return Verdict::Unknown;
+    }
+    // Otherwise compare the whole expressions.
+    let mut decided = true;
+    for (expression, other) in [(a, b), (b, a)] {
+        match missing(expression, other) {
+            (Some(output), _) => return Verdict::Different(output),
+            (None, complete) => decided &= complete,
+        }
+    }
+    if decided {
+        Verdict::Equivalent
+    } else {
+        Verdict::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_peel() {
+        let test_cases = [
+            (
+                "host{1..9}.{us,eu}",
+                "host{1..9}.{us,uk}",
+                "{us,eu}",
+                "{us,uk}",
+            ),
+            ("web{a,b}-x", "web{a,c}-x", "{a,b}", "{a,c}"),
+            ("ab{1,2}cd", "abc{1,2}d", "{1,2}c", "c{1,2}"),
+            ("same", "same", "", ""),
+        ];
+        for (a, b, middle_a, middle_b) in test_cases {
+            let (a, b) = (
+                Expression::try_from(a).unwrap(),
+                Expression::try_from(b).unwrap(),
+            );
+            let peeled = Peeled::new(a.parts(), b.parts());
+            let display =
+                |parts: &[Part<'_>]| parts.iter().map(ToString::to_string).collect::<String>();
+            assert_eq!(
+                (display(&peeled.a), display(&peeled.b)),
+                (middle_a.to_string(), middle_b.to_string())
+            );
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::fmt;
+use std::ops::Range;
+
+/// The kind of problem found while parsing an [`Expression`](crate::Expression).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// An opening brace was never closed.
+    UnclosedBrace,
+
+    /// A closing brace appeared without a matching opening brace.
+    UnexpectedCloseBrace,
+
+    /// The input ended directly after an escape character.
+    TrailingEscape,
+
+    /// A sequence had a stride that could not be parsed, like `{1..5..x}`, in
+    /// a dialect with strict strides.
+    InvalidStride,
+}
+
+impl ParseErrorKind {
+    fn description(self) -> &'static str {
+        match self {
+            ParseErrorKind::UnclosedBrace => "unclosed brace",
+            ParseErrorKind::UnexpectedCloseBrace => "unexpected closing brace",
+            ParseErrorKind::TrailingEscape => "trailing escape character",
+            ParseErrorKind::InvalidStride => "invalid sequence stride",
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.description())
+    }
+}
+
+/// An error produced when an expression fails to parse.
+///
+/// This carries the kind of error, the span of the offending input (in both
+/// bytes and chars), and a description of what was expected there.  The
+/// Display form renders the offending line with a caret underneath the span.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    span: Range<usize>,
+    char_span: Range<usize>,
+    expected: Option<String>,
+    line_number: usize,
+    column: usize,
+    line: String,
+}
+
+impl ParseError {
+    /// Build an error from the full source and the byte span of the problem.
+    pub(crate) fn new(
+        source: &str,
+        kind: ParseErrorKind,
+        span: Range<usize>,
+        expected: Option<String>,
+    ) -> Self {
+        let char_start = source[..span.start].chars().count();
+        let char_span = char_start..char_start + source[span.clone()].chars().count();
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        ParseError {
+            kind,
+            char_span,
+            expected,
+            line_number: source[..span.start].matches('\n').count() + 1,
+            column: source[line_start..span.start].chars().count() + 1,
+            line: source[line_start..line_end].to_owned(),
+            span,
+        }
+    }
+
+    /// The kind of error.
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// The byte span of the offending input.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// The char span of the offending input.
+    pub fn char_span(&self) -> Range<usize> {
+        self.char_span.clone()
+    }
+
+    /// A description of what was expected at the offending input, if anything
+    /// specific was.
+    pub fn expected(&self) -> Option<&str> {
+        self.expected.as_deref()
+    }
+
+    /// The 1-indexed line of the input that the error starts on.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// The 1-indexed char column of the line that the error starts on.
+    pub fn column(&self) -> usize {
+        self.column
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(expected) = &self.expected {
+            write!(f, ", expected {expected}")?;
+        }
+        writeln!(f, " at line {}, column {}:", self.line_number, self.column)?;
+        writeln!(f, "{}", self.line)?;
+        // Only underline the part of the span that fits on this line.
+        let line_remaining = self.line.chars().count() + 1 - self.column;
+        let carets = (self.char_span.end - self.char_span.start)
+            .min(line_remaining)
+            .max(1);
+        write!(
+            f,
+            "{:indent$}{:^<carets$}",
+            "",
+            "",
+            indent = self.column - 1,
+            carets = carets
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// An error produced while expanding a character sequence that steps onto a
+/// codepoint that is not a valid `char`, such as a surrogate.
+///
+/// Iteration may continue after this error, as later items of the sequence may
+/// be valid again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpansionError {
+    pub(crate) sequence: String,
+    pub(crate) span: Option<Range<usize>>,
+    pub(crate) codepoint: u32,
+    pub(crate) index: u128,
+}
+
+impl ExpansionError {
+    /// The character sequence that failed, in its source form, like `{a..z}`.
+    pub fn sequence(&self) -> &str {
+        &self.sequence
+    }
+
+    /// The byte span of the failing sequence in the parsed input, if the
+    /// sequence came from parsed input.
+    pub fn span(&self) -> Option<Range<usize>> {
+        self.span.clone()
+    }
+
+    /// The codepoint that could not be converted to a `char`.
+    pub fn codepoint(&self) -> u32 {
+        self.codepoint
+    }
+
+    /// The index of the failed output in the expansion order, counting every
+    /// output before it, whether it succeeded or failed.
+    pub fn index(&self) -> u128 {
+        self.index
+    }
+}
+
+impl fmt::Display for ExpansionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "character sequence {} produced invalid codepoint U+{:04X}",
+            self.sequence, self.codepoint
+        )?;
+        if let Some(span) = &self.span {
+            write!(f, " at bytes {}..{}", span.start, span.end)?;
+        }
+        write!(f, " for output {}", self.index)
+    }
+}
+
+impl std::error::Error for ExpansionError {}
+
+/// An error produced when building a [`Dialect`](crate::Dialect) whose special
+/// characters conflict.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum DialectError {
+    /// The same character was given more than one role.
+    DuplicateCharacter(char),
+
+    /// The range operator was empty, or contained a digit or another special
+    /// character.
+    InvalidRangeOperator(String),
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialectError::DuplicateCharacter(c) => {
+                write!(f, "character {c:?} was given more than one role")
+            }
+            DialectError::InvalidRangeOperator(range) => {
+                write!(f, "invalid range operator {range:?}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DialectError {}
+
+/// An error produced when a [`Cursor`](crate::Cursor) can't be parsed or
+/// can't be used to resume an expression.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CursorError {
+    /// The string was not a cursor.
+    Invalid(String),
+
+    /// The cursor came from a different expression.
+    FingerprintMismatch {
+        /// The fingerprint of the expression being resumed.
+        expected: u64,
+        /// The fingerprint recorded in the cursor.
+        found: u64,
+    },
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CursorError::Invalid(cursor) => write!(f, "invalid cursor {cursor:?}"),
+            CursorError::FingerprintMismatch { expected, found } => write!(
+                f,
+                "cursor fingerprint {found:016x} does not match expression fingerprint {expected:016x}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
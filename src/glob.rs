@@ -0,0 +1,327 @@
+//! Approximating expressions with shell glob patterns.
+
+use std::collections::{BTreeSet, HashSet};
+use std::fmt;
+
+use crate::{sequence, Expression, Part, Sequence};
+
+/// Parts with at most this many values are approximated from their values.
+const ENUMERATE_LIMIT: u128 = 1 << 16;
+
+/// A shell glob pattern approximating an [`Expression`], created by
+/// [`Expression::to_glob`].
+///
+/// Globs can't express alternations or numeric ranges, so most expressions
+/// can only be approximated, by a pattern that matches every output and
+/// possibly more.  [`is_exact`](Self::is_exact) tells whether the pattern
+/// matches exactly the outputs.
+///
+/// The pattern uses `*`, `?` and bracket expressions, with `\` escaping
+/// special characters outside of brackets, as `fnmatch` does by default.  A
+/// `*` doesn't match `/` when matching paths, so a pattern that approximates
+/// outputs containing `/` may not match all of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Glob {
+    pattern: String,
+    exact: bool,
+}
+
+impl Glob {
+    fn exact() -> Self {
+        Glob {
+            pattern: String::new(),
+            exact: true,
+        }
+    }
+
+    /// The glob pattern.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Whether the pattern matches exactly the outputs of the expression,
+    /// rather than a superset of them.
+    pub fn is_exact(&self) -> bool {
+        self.exact
+    }
+
+    fn push_literal(&mut self, s: &str) {
+        for c in s.chars() {
+            self.push_char(c);
+        }
+    }
+
+    fn push_char(&mut self, c: char) {
+        // Braces aren't special to `fnmatch`, but are to some globs.
+        if matches!(c, '*' | '?' | '[' | '\\' | '{' | '}') {
+            self.pattern.push('\\');
+        }
+        self.pattern.push(c);
+    }
+
+    /// Match any of `chars`, which must not be empty.
+    fn push_class(&mut self, chars: &BTreeSet<char>) {
+        match chars.iter().collect::<Vec<_>>()[..] {
+            [&c] => self.push_char(c),
+            _ => match bracket(chars) {
+                Some(bracket) => self.pattern.push_str(&bracket),
+                None => {
+                    self.pattern.push('?');
+                    self.exact = false;
+                }
+            },
+        }
+    }
+
+    /// Match anything, which is never exact.
+    fn push_star(&mut self) {
+        // `**` means something else to some globs.
+        if !self.pattern.ends_with('*') || self.pattern.ends_with("\\*") {
+            self.pattern.push('*');
+        }
+        self.exact = false;
+    }
+
+    fn push(&mut self, glob: Glob) {
+        if glob.pattern.starts_with('*') {
+            self.push_star();
+            self.pattern.push_str(&glob.pattern[1..]);
+        } else {
+            self.pattern.push_str(&glob.pattern);
+        }
+        self.exact &= glob.exact;
+    }
+}
+
+impl fmt::Display for Glob {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// A bracket expression matching any of `chars`, if one can be written.
+fn bracket(chars: &BTreeSet<char>) -> Option<String> {
+    // Group chars into runs of consecutive chars.
+    let mut runs: Vec<(char, char)> = Vec::new();
+    for &c in chars {
+        match runs.last_mut() {
+            Some((_, end)) if u32::from(*end) + 1 == u32::from(c) => *end = c,
+            _ => runs.push((c, c)),
+        }
+    }
+    // Chars that are special in brackets can't be the ends of ranges, and
+    // have to go where they are taken literally.
+    let special = |c: char| matches!(c, ']' | '-' | '!' | '^' | '[' | '\\');
+    let mut specials = BTreeSet::new();
+    let mut items = String::new();
+    for (mut start, mut end) in runs {
+        while start <= end && special(start) {
+            specials.insert(start);
+            start = char::from_u32(u32::from(start) + 1)?;
+        }
+        while start <= end && special(end) {
+            specials.insert(end);
+            end = char::from_u32(u32::from(end) - 1)?;
+        }
+        match u32::from(end).checked_sub(u32::from(start)) {
+            None => {}
+            Some(0) => items.push(start),
+            Some(1) => items.extend([start, end]),
+            Some(_) => items.extend([start, '-', end]),
+        }
+    }
+    // Whether `\` escapes in brackets differs between implementations.
+    if specials.contains(&'\\') {
+        return None;
+    }
+    // `]` is literal first, and `-` first or last.  `!` and `^` negate the
+    // bracket when they come first, and `[` starts a class before `:`.
+    let mut bracket = String::from("[");
+    if specials.remove(&']') {
+        bracket.push(']');
+    } else if items.is_empty() && specials.remove(&'-') {
+        bracket.push('-');
+    }
+    if bracket.len() == 1
+        && items.is_empty()
+        && (specials.contains(&'!') || specials.contains(&'^'))
+    {
+        let leading = *specials.iter().find(|&&c| c != '!' && c != '^')?;
+        bracket.push(leading);
+        specials.remove(&leading);
+    }
+    bracket.push_str(&items);
+    for c in ['!', '^', '['] {
+        if specials.remove(&c) {
+            bracket.push(c);
+        }
+    }
+    if specials.remove(&'-') {
+        bracket.push('-');
+    }
+    bracket.push(']');
+    Some(bracket)
+}
+
+/// Approximate a set of values, which must not be empty.
+fn values(values: &HashSet<String>) -> Glob {
+    let values: Vec<Vec<char>> = values.iter().map(|value| value.chars().collect()).collect();
+    let min_len = values.iter().map(Vec::len).min().unwrap_or(0);
+    let max_len = values.iter().map(Vec::len).max().unwrap_or(0);
+    let position = |i: usize| values.iter().map(|value| value[i]).collect::<BTreeSet<_>>();
+    let mut glob = Glob::exact();
+    if min_len == max_len {
+        // The values are exactly matched by a class at each position if every
+        // combination of chars is a value.
+        let mut combinations = 1u128;
+        for i in 0..min_len {
+            let chars = position(i);
+            combinations = combinations.saturating_mul(chars.len() as u128);
+            glob.push_class(&chars);
+        }
+        glob.exact &= combinations == values.len() as u128;
+        return glob;
+    }
+    // Otherwise keep what the values have in common at each end.
+    let prefix = (0..min_len).take_while(|&i| position(i).len() == 1).count();
+    let suffix = (0..min_len - prefix)
+        .take_while(|&i| {
+            let chars: BTreeSet<_> = values
+                .iter()
+                .map(|value| value[value.len() - 1 - i])
+                .collect();
+            chars.len() == 1
+        })
+        .count();
+    glob.push_literal(&values[0][..prefix].iter().collect::<String>());
+    if prefix + suffix < min_len {
+        glob.push_class(&position(prefix));
+    }
+    glob.push_star();
+    let last = &values[0];
+    glob.push_literal(&last[last.len() - suffix..].iter().collect::<String>());
+    glob
+}
+
+pub(crate) fn expression(expression: &Expression<'_>) -> Glob {
+    let mut glob = Glob::exact();
+    if expression.count() == Some(0) {
+        // Nothing can match exactly no strings.
+        glob.exact = false;
+        return glob;
+    }
+    for part in expression.parts() {
+        glob.push(self::part(part));
+    }
+    glob
+}
+
+fn part(part: &Part<'_>) -> Glob {
+    match part {
+        Part::Plain(plain) => {
+            let mut glob = Glob::exact();
+            glob.push_literal(plain);
+            glob
+        }
+        Part::Sequence(Sequence::Char { sequence, .. }) => {
+            // Values in the surrogate range are errors rather than outputs.
+            let chars: BTreeSet<_> = (0..sequence.count())
+                .filter_map(|n| sequence.value(n).ok())
+                .collect();
+            let mut glob = Glob::exact();
+            if chars.is_empty() {
+                glob.exact = false;
+            } else {
+                glob.push_class(&chars);
+            }
+            glob
+        }
+        Part::Expression(expression) => self::expression(expression),
+        _ => match part.count() {
+            Some(count) if count <= ENUMERATE_LIMIT => {
+                let expression = Expression {
+                    parts: vec![part.clone()],
+                    dialect: None,
+                };
+                let outputs: HashSet<String> = expression
+                    .iter()
+                    .filter_map(|output| output.ok().map(String::from))
+                    .collect();
+                if outputs.is_empty() {
+                    return self::expression(&expression);
+                }
+                values(&outputs)
+            }
+            _ => match part {
+                Part::Sequence(Sequence::Int { width, sequence }) => int_sequence(*width, sequence),
+                Part::List(list) => {
+                    let mut items = list.items().iter().filter(|item| item.count() != Some(0));
+                    match (items.next(), items.next()) {
+                        (Some(item), None) => self::part(item),
+                        _ => {
+                            let mut glob = Glob::exact();
+                            glob.push_star();
+                            glob
+                        }
+                    }
+                }
+                _ => unreachable!("only sequences and lists are enumerated"),
+            },
+        },
+    }
+}
+
+/// Approximate a numeric sequence with too many values to enumerate.
+fn int_sequence(width: Option<usize>, sequence: &sequence::Sequence<i64>) -> Glob {
+    let format = |number: i64| match width {
+        Some(width) => format!("{number:0width$}"),
+        None => number.to_string(),
+    };
+    let low = sequence.start.min(sequence.end);
+    let high = sequence.start.max(sequence.end);
+    let (low, high) = (format(low), format(high));
+    let mut glob = Glob::exact();
+    if sequence.start.min(sequence.end) < 0 || low.len() != high.len() {
+        if sequence.start.min(sequence.end) >= 0 {
+            glob.push_class(&('0'..='9').collect());
+        }
+        glob.push_star();
+        return glob;
+    }
+    // Each position takes the digits between the ends, until the ends first
+    // differ, after which it takes any digit.
+    let mut combinations = 1u128;
+    let mut differed = false;
+    for (low, high) in low.chars().zip(high.chars()) {
+        let digits: BTreeSet<_> = if differed { '0'..='9' } else { low..=high }.collect();
+        differed |= low != high;
+        combinations = combinations.saturating_mul(digits.len() as u128);
+        glob.push_class(&digits);
+    }
+    glob.exact = combinations == sequence.count();
+    glob
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bracket() {
+        let test_cases = [
+            ("abcdxz", Some("[a-dxz]")),
+            ("]a-", Some("[]a-]")),
+            ("!a", Some("[a!]")),
+            ("-!", Some("[-!]")),
+            ("!^[", Some("[[!^]")),
+            ("[]", Some("[][]")),
+            ("[\\]", None),
+            ("0123456789", Some("[0-9]")),
+            ("!^", None),
+        ];
+        for (chars, expected) in test_cases {
+            let chars: BTreeSet<_> = chars.chars().collect();
+            assert_eq!(bracket(&chars).as_deref(), expected, "{chars:?}");
+        }
+    }
+}
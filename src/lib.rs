@@ -1,154 +1,610 @@
-use std::fmt::Debug;
+use std::borrow::Cow;
+use std::fmt::{self, Debug};
+use std::io;
+use std::ops::Range;
 use std::str::FromStr;
-use std::{borrow::Cow, char::CharTryFromError, iter};
-
-use itertools::{Itertools, MultiProduct};
-use nom::error::{convert_error, VerboseError};
+use std::sync::Arc;
 
+mod builder;
+mod compress;
+mod cursor;
+mod dialect;
+mod equivalent;
+mod error;
+mod glob;
+mod order;
+#[cfg(feature = "rayon")]
+mod par;
 mod parser;
-mod sequence;
+mod regex;
+#[cfg(feature = "rand")]
+mod sample;
+pub mod sequence;
+mod shape;
+mod shuffle;
+mod simplify;
+mod unique;
+pub mod visit;
+
+pub use builder::ExpressionBuilder;
+pub use compress::compress;
+pub use cursor::Cursor;
+pub use dialect::{Dialect, DialectBuilder};
+pub use equivalent::{Equivalence, Verdict};
+pub use error::{CursorError, DialectError, ExpansionError, ParseError, ParseErrorKind};
+pub use glob::Glob;
+pub use order::{Order, Sorted};
+#[cfg(feature = "rayon")]
+pub use par::ParIter;
+#[cfg(feature = "rand")]
+pub use sample::Sample;
+pub use shuffle::Shuffled;
+pub use unique::Unique;
+
+use shape::Shape;
 
-/// {a,b,c}
+/// The dialect used by expressions that weren't given one.
+static DEFAULT_DIALECT: Dialect = Dialect::bexpand();
+
+/// A list of alternatives, like `{a,b,c}`, which produces all the values of
+/// each of its items in turn.
 #[derive(Clone, Debug)]
-struct List<'a>(Vec<Part<'a>>);
+pub struct List<'a>(Vec<Part<'a>>);
 
 impl<'a> List<'a> {
-    fn into_owned(self) -> List<'static> {
-        List(self.0.into_iter().map(Part::into_owned).collect())
+    /// Create a list from its items.  Each item is usually a
+    /// [`Part::Expression`], or an empty [`Part::Plain`].
+    pub fn new(items: Vec<Part<'a>>) -> Self {
+        List(items)
+    }
+
+    /// The items of the list.
+    pub fn items(&self) -> &[Part<'a>] {
+        &self.0
     }
-}
 
-impl<'a> IntoIterator for List<'a> {
-    type Item = Result<Cow<'a, str>, CharTryFromError>;
+    /// The items of the list, mutably.
+    pub fn items_mut(&mut self) -> &mut Vec<Part<'a>> {
+        &mut self.0
+    }
 
-    type IntoIter = iter::Flatten<<Vec<Part<'a>> as IntoIterator>::IntoIter>;
+    /// The number of values the list produces, or `None` if that overflows a
+    /// `u128`.
+    pub fn count(&self) -> Option<u128> {
+        self.0
+            .iter()
+            .try_fold(0u128, |count, item| count.checked_add(item.count()?))
+    }
 
-    fn into_iter(self) -> Self::IntoIter {
-        self.0.into_iter().flatten()
+    fn into_owned(self) -> List<'static> {
+        List(self.0.into_iter().map(Part::into_owned).collect())
     }
 }
 
-impl std::fmt::Display for List<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("{")?;
+impl List<'_> {
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, dialect: &Dialect) -> fmt::Result {
+        // Bash takes braces without a separator literally, so a single item is
+        // written on its own instead.
+        if let [item] = &self.0[..] {
+            if dialect.is_bash() {
+                return item.fmt_with(f, dialect);
+            }
+            // Plain text alone in braces could be mistaken for a sequence, so
+            // its range operators are escaped.
+            let plain = match item {
+                Part::Plain(plain) => Some(plain),
+                Part::Expression(expression) => match expression.parts() {
+                    [Part::Plain(plain)] => Some(plain),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(plain) = plain {
+                write!(f, "{}", dialect.open())?;
+                fmt_plain(f, plain, dialect, true)?;
+                return write!(f, "{}", dialect.close());
+            }
+        }
+        write!(f, "{}", dialect.open())?;
         let mut parts = self.0.iter();
         if let Some(part) = parts.next() {
-            write!(f, "{part}")?;
+            part.fmt_with(f, dialect)?;
         }
         for part in parts {
-            write!(f, ",{part}")?;
+            write!(f, "{}", dialect.separator())?;
+            part.fmt_with(f, dialect)?;
         }
-        f.write_str("}")?;
+        write!(f, "{}", dialect.close())?;
         Ok(())
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-enum Sequence {
-    Int {
-        width: Option<usize>,
-        sequence: sequence::Sequence<i64>,
-    },
-    Char(sequence::Sequence<char>),
+impl fmt::Display for List<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &DEFAULT_DIALECT)
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
-enum SequenceIterator {
+/// A numeric or character sequence, like `{1..10}` or `{a..z..2}`.
+#[derive(Clone, Debug)]
+pub enum Sequence {
+    /// A numeric sequence.
     Int {
+        /// The width to pad each number to with leading zeroes, if any.
         width: Option<usize>,
-        sequence: sequence::SequenceIterator<i64>,
+        sequence: sequence::Sequence<i64>,
+    },
+    /// A character sequence.
+    Char {
+        sequence: sequence::Sequence<char>,
+        /// The byte span in the parsed input, if any.
+        span: Option<Range<usize>>,
     },
-    Char(sequence::SequenceIterator<char>),
 }
 
-impl IntoIterator for Sequence {
-    type Item = Result<String, CharTryFromError>;
-
-    type IntoIter = SequenceIterator;
-
-    fn into_iter(self) -> Self::IntoIter {
+impl Sequence {
+    /// The number of values the sequence produces, including any invalid
+    /// characters.
+    pub fn count(&self) -> u128 {
         match self {
-            Sequence::Int { width, sequence } => SequenceIterator::Int {
-                width,
-                sequence: sequence.into_iter(),
-            },
-            Sequence::Char(s) => SequenceIterator::Char(s.into_iter()),
+            Sequence::Int { sequence, .. } => sequence.count(),
+            Sequence::Char { sequence, .. } => sequence.count(),
         }
     }
 }
 
-impl Iterator for SequenceIterator {
-    type Item = Result<String, <u32 as TryInto<char>>::Error>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            SequenceIterator::Int { width, sequence } => {
-                sequence.next().map(|number| match *width {
-                    Some(width) => Ok(format!(
-                        "{number:0width$}",
-                        number = number.unwrap(),
-                        width = width,
-                    )),
-                    None => Ok(number.unwrap().to_string()),
-                })
-            }
-            SequenceIterator::Char(i) => i.next().map(|r| r.map(|c| c.to_string())),
-        }
-    }
-}
-
-impl std::fmt::Display for Sequence {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("{")?;
+impl Sequence {
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, dialect: &Dialect) -> fmt::Result {
+        let range = dialect.range_operator();
+        write!(f, "{}", dialect.open())?;
         match *self {
             Self::Int {
                 width,
                 sequence: sequence::Sequence { start, end, incr },
             } => {
-                if width.is_some() {
-                    f.write_str("=")?;
+                match width {
+                    // Bash infers the width from leading zeroes.
+                    Some(width) if dialect.is_bash() => {
+                        write!(f, "{start:0width$}{range}{end:0width$}")?;
+                    }
+                    // The parsed width is that of the wider endpoint, so pad the
+                    // end if the width is wider than both.
+                    Some(width) => {
+                        let natural = start.to_string().len().max(end.to_string().len());
+                        if width > natural {
+                            write!(f, "={start}{range}{end:0width$}")?;
+                        } else {
+                            write!(f, "={start}{range}{end}")?;
+                        }
+                    }
+                    None => write!(f, "{start}{range}{end}")?,
                 }
-                write!(f, "{start}..{end}")?;
                 if incr != 1 {
-                    write!(f, "..{incr}")?;
+                    write!(f, "{range}{incr}")?;
                 }
             }
-            Self::Char(sequence::Sequence { start, end, incr }) => {
-                let escaped = ",.{}\\";
-                if escaped.contains(start) {
-                    f.write_str("\\")?;
+            Self::Char {
+                sequence: sequence::Sequence { start, end, incr },
+                ..
+            } => {
+                // Escape anything that would be mistaken for syntax, including a
+                // leading digit that would make this a numeric sequence.
+                let escaped =
+                    |c: char| !dialect.is_bash() && (dialect.is_special(c) || range.starts_with(c));
+                if escaped(start) || (!dialect.is_bash() && start.is_ascii_digit()) {
+                    write!(f, "{}", dialect.escape())?;
                 }
-                write!(f, "{start}..")?;
-                if escaped.contains(end) {
-                    f.write_str("\\")?;
+                write!(f, "{start}{range}")?;
+                if escaped(end) {
+                    write!(f, "{}", dialect.escape())?;
                 }
                 write!(f, "{end}")?;
                 if incr != 1 {
-                    write!(f, "..{incr}")?;
+                    write!(f, "{range}{incr}")?;
                 }
             }
         }
-        f.write_str("}")?;
+        write!(f, "{}", dialect.close())?;
         Ok(())
     }
 }
 
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &DEFAULT_DIALECT)
+    }
+}
+
 /// Bash-style brace expression. Can be created using TryFrom (like
 /// `"foo{bar,baz}biz".try_into()`) or via FromStr
 /// (`"foo{bar,baz}biz".parse()`). TryFrom is preferred, because it will avoid
 /// unnecessary allocations wherever possible, and tie to the lifetime of the
 /// incoming string. FromStr will make String clones in unnecessary places.
+///
+/// An expression remembers the dialect it was parsed with, and displays itself
+/// in that same dialect.
 #[derive(Clone, Debug)]
-pub struct Expression<'a>(Vec<Part<'a>>);
+pub struct Expression<'a> {
+    parts: Vec<Part<'a>>,
+    /// The dialect, if it's not the default.
+    dialect: Option<Arc<Dialect>>,
+}
 
 impl<'a> Expression<'a> {
+    /// Parse an expression using the given dialect.  `TryFrom` and `FromStr`
+    /// use the default dialect.
+    pub fn parse_with(value: &'a str, dialect: &Dialect) -> Result<Self, ParseError> {
+        match parser::Parser::new(value, dialect).expression() {
+            Ok((_, expression)) => Ok(expression),
+            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => {
+                let start = value.len() - e.input.len();
+                let kind = e
+                    .kind
+                    .expect("only failures with a kind may escape the parser");
+                Err(ParseError::new(
+                    value,
+                    kind,
+                    start..start + e.len,
+                    e.expected,
+                ))
+            }
+            Err(nom::Err::Incomplete(_)) => panic!("Somehow got an incomplete"),
+        }
+    }
+
+    /// A builder to construct an expression part by part.
+    pub fn builder() -> ExpressionBuilder<'a> {
+        ExpressionBuilder::new()
+    }
+
+    /// The parts of the expression, which produce the cartesian product of
+    /// their values.
+    pub fn parts(&self) -> &[Part<'a>] {
+        &self.parts
+    }
+
+    /// The parts of the expression, mutably.
+    pub fn parts_mut(&mut self) -> &mut Vec<Part<'a>> {
+        &mut self.parts
+    }
+
+    /// The dialect this expression is displayed with.
+    pub fn dialect(&self) -> &Dialect {
+        self.dialect.as_deref().unwrap_or(&DEFAULT_DIALECT)
+    }
+
+    /// The exact number of outputs the expression produces, counting failed
+    /// outputs, or `None` if that overflows a `u128`.  An expression without
+    /// any parts produces no outputs.
+    pub fn count(&self) -> Option<u128> {
+        // A part without any values makes the count zero, even if the other
+        // parts overflow.
+        let mut empty = self.parts.is_empty();
+        let mut count = Some(1u128);
+        for part in &self.parts {
+            match part.count() {
+                Some(0) => empty = true,
+                part => count = count.zip(part).and_then(|(a, b)| a.checked_mul(b)),
+            }
+        }
+        if empty {
+            Some(0)
+        } else {
+            count
+        }
+    }
+
+    /// Iterate over the outputs without consuming the expression.  Plain
+    /// parts of the expression are borrowed rather than copied wherever an
+    /// output is made of just one of them.
+    pub fn iter(&self) -> ExpressionIterator<'_> {
+        ExpressionIterator::new(Cow::Borrowed(self))
+    }
+
+    /// Output `n` of the expansion order, or `None` if there are fewer outputs
+    /// than that.  This only descends through the expression once, rather
+    /// than producing every output before it.
+    pub fn nth_expansion(&self, n: u128) -> Option<Result<Cow<'_, str>, ExpansionError>> {
+        Shape::of_expression(self).get(self, n, false, &|plain| Cow::Borrowed(plain))
+    }
+
+    /// Sample `n` outputs uniformly at random, with replacement, so the same
+    /// output may come up more than once.  Only the sampled outputs are ever
+    /// produced, so this works for expressions of any size, and is
+    /// reproducible with a seeded `rng`.
+    #[cfg(feature = "rand")]
+    pub fn sample<R: rand::Rng>(&self, rng: R, n: usize) -> Sample<'_, 'a, R> {
+        Sample::new(self, rng, n, false)
+    }
+
+    /// Sample `n` outputs uniformly at random, without replacement, so each
+    /// output index comes up at most once.  If the expression has fewer than
+    /// `n` outputs, all of them are produced in a random order.
+    ///
+    /// The indices sampled so far are remembered, and sampling slows down as
+    /// `n` approaches the number of outputs.
+    #[cfg(feature = "rand")]
+    pub fn sample_distinct<R: rand::Rng>(&self, rng: R, n: usize) -> Sample<'_, 'a, R> {
+        Sample::new(self, rng, n, true)
+    }
+
+    /// Iterate over shard `k` of `n` of the outputs, which is a contiguous
+    /// run of the expansion order.  The shards don't overlap, cover every
+    /// output between them, and differ in size by at most one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` isn't less than `n`, or if there are more outputs than fit
+    /// in a `u128`.
+    pub fn shard(self, k: usize, n: usize) -> ExpressionIterator<'a> {
+        assert!(k < n, "shard {k} is out of range for {n} shards");
+        let count = self.count().expect("too many outputs to shard");
+        // This is `count * k / n`, without overflowing.
+        let boundary = |k: usize| {
+            let (k, n) = (k as u128, n as u128);
+            k * (count / n) + k * (count % n) / n
+        };
+        let mut iter = self.into_iter();
+        iter.front = boundary(k);
+        iter.back = count - boundary(k + 1);
+        iter
+    }
+
+    /// Call `f` with every output in order, stopping at the first error.
+    ///
+    /// Unlike iterating, this builds every output in one reused buffer, and
+    /// only rewrites the end of it that changed since the previous output, so
+    /// it is much faster for large expansions.
+    pub fn expand_each<F>(&self, f: F) -> Result<(), ExpansionError>
+    where
+        F: FnMut(&str),
+    {
+        Shape::of_expression(self).for_each(self, f)
+    }
+
+    /// Write every output to `writer`, separated by `separator`, as with
+    /// [`expand_each`](Self::expand_each).  An expansion error stops writing,
+    /// and is returned as an [`io::ErrorKind::InvalidData`] error.
+    ///
+    /// Each output is written with its own call, so `writer` should usually
+    /// be buffered.
+    pub fn write_all<W>(&self, writer: &mut W, separator: &str) -> io::Result<()>
+    where
+        W: io::Write + ?Sized,
+    {
+        let mut result = Ok(());
+        let mut first = true;
+        let expanded = self.expand_each(|output| {
+            if result.is_err() {
+                return;
+            }
+            if !first {
+                result = writer.write_all(separator.as_bytes());
+            }
+            first = false;
+            if result.is_ok() {
+                result = writer.write_all(output.as_bytes());
+            }
+        });
+        result?;
+        expanded.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    /// A stable hash of the expression, which [`Cursor`]s are checked
+    /// against.  Expressions with the same parts have the same fingerprint,
+    /// whatever their dialect.
+    pub fn fingerprint(&self) -> u64 {
+        cursor::fingerprint(self)
+    }
+
+    /// Resume iterating where an iterator over this same expression left off,
+    /// as recorded by [`ExpressionIterator::cursor`].
+    pub fn iter_from(self, cursor: Cursor) -> Result<ExpressionIterator<'a>, CursorError> {
+        let expected = self.fingerprint();
+        if cursor.fingerprint != expected {
+            return Err(CursorError::FingerprintMismatch {
+                expected,
+                found: cursor.fingerprint,
+            });
+        }
+        let mut iter = self.into_iter();
+        iter.back = match iter.shape.count() {
+            Some(count) => cursor.back.min(count),
+            None => cursor.back,
+        };
+        iter.skip_to(cursor.front);
+        Ok(iter)
+    }
+
+    /// A rayon parallel iterator over the outputs, which come out in the
+    /// usual order when collected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are more outputs than fit in a `usize`.
+    #[cfg(feature = "rayon")]
+    pub fn par_iter(&self) -> ParIter<'_, 'a> {
+        ParIter::new(self)
+    }
+
+    /// Iterate over every output exactly once, in a pseudo-random order that
+    /// is determined by `seed`.  This uses constant memory, unlike collecting
+    /// and shuffling the outputs.
+    ///
+    /// If there are more outputs than fit in a `u128`, only the first
+    /// 2^128 of them are shuffled.
+    pub fn shuffled(&self, seed: u64) -> Shuffled<'_, 'a> {
+        Shuffled::new(self, seed)
+    }
+
+    /// Iterate over the outputs sorted in `order`, rather than in expansion
+    /// order.  Outputs are streamed in runs where the leading parts of the
+    /// expression decide the order, and are all buffered otherwise.
+    ///
+    /// ```
+    /// use bexpand::{Expression, Order};
+    ///
+    /// let expression = Expression::try_from("v{10,9,1}.{b,a}").unwrap();
+    /// let natural: Vec<_> = expression.sorted(Order::Natural).map(Result::unwrap).collect();
+    /// assert_eq!(natural, ["v1.a", "v1.b", "v9.a", "v9.b", "v10.a", "v10.b"]);
+    /// ```
+    pub fn sorted(&self, order: Order) -> Sorted<'_> {
+        Sorted::new(self.iter(), order)
+    }
+
+    /// Whether `input` is one of the outputs.  This matches the string
+    /// against the expression, checking numeric sequences arithmetically, so
+    /// it's cheap however many outputs there are.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let expression = Expression::try_from("web{=1..40}.{us,eu}-prod").unwrap();
+    /// assert!(expression.matches("web07.eu-prod"));
+    /// assert!(!expression.matches("web7.eu-prod"));
+    /// assert!(!expression.matches("web41.us-prod"));
+    /// ```
+    pub fn matches(&self, input: &str) -> bool {
+        shape::prefix_lengths(self, input).contains(&input.len())
+    }
+
+    /// A regular expression that matches exactly the outputs, for tools that
+    /// take a pattern rather than a list.  Lists become alternations,
+    /// character sequences become character classes, and numeric sequences
+    /// become exact numeric ranges, except that a sequence with a stride is
+    /// spelled out in full.
+    ///
+    /// The pattern isn't anchored, so wrap it in `^(?:...)$` to match whole
+    /// strings.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let expression = Expression::try_from("web{=1..40}.{us,eu}").unwrap();
+    /// assert_eq!(
+    ///     expression.to_regex_string(),
+    ///     r"web(?:0[1-9]|[1-3][0-9]|40)\.(?:us|eu)",
+    /// );
+    /// ```
+    pub fn to_regex_string(&self) -> String {
+        regex::expression(self)
+    }
+
+    /// The closest shell glob pattern to the expression, for tools that
+    /// understand globs but not braces.  The glob matches every output, and
+    /// [`Glob::is_exact`] tells whether it matches anything else.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let glob = Expression::try_from("log-{=1..31}.txt").unwrap().to_glob();
+    /// assert_eq!(glob.pattern(), "log-[0-3][0-9].txt");
+    /// assert!(!glob.is_exact());
+    /// let glob = Expression::try_from("{a,b}{1..3}").unwrap().to_glob();
+    /// assert_eq!(glob.pattern(), "[ab][1-3]");
+    /// assert!(glob.is_exact());
+    /// ```
+    pub fn to_glob(&self) -> Glob {
+        glob::expression(self)
+    }
+
+    /// An equivalent expression with the same outputs in the same order, with
+    /// as little structure as possible.  Nested expressions and lists of one
+    /// item are flattened into their parent, adjacent plain strings are
+    /// joined, and list items that are consecutive numbers or chars become a
+    /// sequence.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let expression = Expression::try_from("{web,{db,cache}}-{{x}}{1,2,3}").unwrap();
+    /// assert_eq!(expression.simplify().to_string(), "{web,db,cache}-x{1..3}");
+    /// ```
+    pub fn simplify(&self) -> Expression<'a> {
+        simplify::expression(self, false)
+    }
+
+    /// An equivalent expression in a canonical form, which produces the same
+    /// outputs as [`simplify`](Self::simplify) would, but maybe in a
+    /// different order.  List items are sorted, sequences count upwards, and
+    /// numbers and ranges that continue each other are merged into a single
+    /// sequence, so that lists of the same values are written the same way.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let expression = Expression::try_from("{b,{10..4..3},a}{{4..6},{1..3}}").unwrap();
+    /// assert_eq!(expression.canonicalize().to_string(), "{a,b,{4..10..3}}{1..6}");
+    /// ```
+    pub fn canonicalize(&self) -> Expression<'a> {
+        simplify::expression(self, true)
+    }
+
+    /// Whether `other` produces the same outputs, either in the same order or
+    /// as a set, depending on `mode`.  This is decided from the structure of
+    /// the expressions where possible, by comparing their simplified or
+    /// canonical forms without the parts they have in common, and otherwise
+    /// by enumerating a bounded number of outputs.  If the expressions differ,
+    /// the verdict has an output that shows it, and if they have too many
+    /// outputs to tell, it's [`Verdict::Unknown`].
+    ///
+    /// ```
+    /// use bexpand::{Equivalence, Expression, Verdict};
+    ///
+    /// let old = Expression::try_from("host{1..1000000000}.{us,eu}").unwrap();
+    /// let new = Expression::try_from("host{{1..500000000},{500000001..1000000000}}.{us,eu}").unwrap();
+    /// assert_eq!(old.equivalent(&new, Equivalence::Sequence), Verdict::Equivalent);
+    ///
+    /// let new = Expression::try_from("host{1..1000000000}.{eu,us}").unwrap();
+    /// assert_eq!(
+    ///     old.equivalent(&new, Equivalence::Sequence),
+    ///     Verdict::Different("host1.us".to_string()),
+    /// );
+    /// assert_eq!(old.equivalent(&new, Equivalence::Set), Verdict::Equivalent);
+    /// ```
+    pub fn equivalent(&self, other: &Expression<'_>, mode: Equivalence) -> Verdict {
+        equivalent::equivalent(self, other, mode)
+    }
+
+    /// The index of the first output equal to `output`, if any.
+    pub fn position_of(&self, output: &str) -> Option<u128> {
+        self.positions_of(output, 1).into_iter().next()
+    }
+
+    /// The indices of the first `limit` outputs equal to `output`, in
+    /// ascending order, as an expression like `{a,a}` may produce the same
+    /// output more than once. This matches the string against the
+    /// expression, rather than producing every output, and takes time
+    /// proportional to `limit` rather than to the number of matches, which
+    /// can grow exponentially with the number of ambiguous parts.
+    ///
+    /// Indices that overflow a `u128` are left out.
+    pub fn positions_of(&self, output: &str, limit: usize) -> Vec<u128> {
+        Shape::of_expression(self)
+            .prefixes(self, output, limit)
+            .into_iter()
+            .filter(|&(len, _)| len == output.len())
+            .filter_map(|(_, index)| index)
+            .collect()
+    }
+
     fn into_owned(self) -> Expression<'static> {
-        Expression(self.0.into_iter().map(Part::into_owned).collect())
+        Expression {
+            parts: self.parts.into_iter().map(Part::into_owned).collect(),
+            dialect: self.dialect,
+        }
+    }
+
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, dialect: &Dialect) -> fmt::Result {
+        for part in &self.parts {
+            part.fmt_with(f, dialect)?;
+        }
+        Ok(())
     }
 }
 
 impl FromStr for Expression<'static> {
-    type Err = String;
+    type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let expression: Expression = s.try_into()?;
@@ -157,69 +613,204 @@ impl FromStr for Expression<'static> {
 }
 
 impl<'a> TryFrom<&'a str> for Expression<'a> {
-    type Error = String;
+    type Error = ParseError;
 
     fn try_from(value: &'a str) -> Result<Self, Self::Error> {
-        let output = parser::expression::<VerboseError<&str>>(value);
-        match output {
-            Ok((_, expression)) => Ok(expression),
-            Err(nom::Err::Error(e) | nom::Err::Failure(e)) => return Err(convert_error(value, e)),
-            _ => panic!("Somehow got an incomplete"),
-        }
+        Expression::parse_with(value, &Dialect::default())
     }
 }
 
 impl<'a> IntoIterator for Expression<'a> {
-    type Item = Result<Cow<'a, str>, CharTryFromError>;
+    type Item = Result<Cow<'a, str>, ExpansionError>;
 
     type IntoIter = ExpressionIterator<'a>;
 
     fn into_iter(self) -> Self::IntoIter {
-        ExpressionIterator(self.0.into_iter().multi_cartesian_product())
+        ExpressionIterator::new(Cow::Owned(self))
     }
 }
 
-impl std::fmt::Display for Expression<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for part in &self.0 {
-            write!(f, "{part}")?;
-        }
-        Ok(())
+impl<'e> IntoIterator for &'e Expression<'_> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    type IntoIter = ExpressionIterator<'e>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl fmt::Display for Expression<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, self.dialect())
     }
 }
 
+/// Iterates over the outputs of an [`Expression`].
+///
+/// Each output is decoded directly from its index, so the iterator can seek
+/// to any output cheaply with [`skip_to`](Self::skip_to) or [`Iterator::nth`],
+/// and can be iterated from the back just as cheaply.
+///
+/// The size hint is exact whenever the remaining count fits in a `usize`.
+/// Otherwise, [`remaining`](Self::remaining) has the exact count.
+/// [`ExactSizeIterator`] is deliberately not implemented, as its `len` would
+/// have to panic for expressions with more outputs than fit in a `usize`,
+/// which even a short expression like `{1..4294967296}{1..4294967296}` has on
+/// 64-bit targets.
 #[derive(Clone, Debug)]
-pub struct ExpressionIterator<'a>(MultiProduct<PartIterator<'a>>);
+pub struct ExpressionIterator<'a> {
+    /// The expression, which is borrowed by [`Expression::iter`], and owned
+    /// when iterating an expression by value.
+    expression: Cow<'a, Expression<'a>>,
+    shape: Shape,
+    /// The index of the next output.
+    front: u128,
+    /// The number of outputs taken from the back.
+    back: u128,
+}
+
+impl<'a> ExpressionIterator<'a> {
+    fn new(expression: Cow<'a, Expression<'a>>) -> Self {
+        ExpressionIterator {
+            shape: Shape::of_expression(&expression),
+            expression,
+            front: 0,
+            back: 0,
+        }
+    }
+
+    /// Decode output `n`, counting from the back if `rev` is set.  Plain
+    /// parts of a borrowed expression are borrowed in the output too.
+    fn get(&self, n: u128, rev: bool) -> Option<Result<Cow<'a, str>, ExpansionError>> {
+        match self.expression {
+            Cow::Borrowed(expression) => self
+                .shape
+                .get(expression, n, rev, &|plain| Cow::Borrowed(plain.as_ref())),
+            Cow::Owned(ref expression) => self.shape.get(expression, n, rev, &Cow::clone),
+        }
+    }
+
+    /// The number of outputs left, or `None` if that overflows a `u128`.
+    pub fn remaining(&self) -> Option<u128> {
+        self.shape
+            .count()
+            .map(|count| count - self.front - self.back)
+    }
+
+    /// The current position, to resume from later with
+    /// [`Expression::iter_from`].
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            fingerprint: self.expression.fingerprint(),
+            front: self.front,
+            back: self.back,
+        }
+    }
+
+    /// Seek so that the next output is output `n` of the expansion order.
+    /// This may seek backwards as well as forwards.  Seeking past the end, or
+    /// past any outputs already taken from the back, leaves the iterator
+    /// exhausted.
+    pub fn skip_to(&mut self, n: u128) {
+        self.front = match self.shape.count() {
+            Some(count) => n.min(count - self.back),
+            None => n,
+        };
+    }
+
+    /// Skip any output that was already produced, keeping the first of each.
+    ///
+    /// The expression is analyzed to find which of its parts can produce
+    /// duplicates at all, and only their outputs are remembered, so this is
+    /// free for expressions that can't produce duplicates.
+    ///
+    /// ```
+    /// use bexpand::Expression;
+    ///
+    /// let expression = Expression::try_from("{a,ab}{bc,c}").unwrap();
+    /// let outputs: Vec<_> = expression.iter().unique().map(Result::unwrap).collect();
+    /// assert_eq!(outputs, ["abc", "ac", "abbc"]);
+    /// ```
+    pub fn unique(self) -> Unique<'a> {
+        Unique::new(self)
+    }
+}
 
 impl<'a> Iterator for ExpressionIterator<'a> {
-    type Item = Result<Cow<'a, str>, CharTryFromError>;
+    type Item = Result<Cow<'a, str>, ExpansionError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.0.next().map(|parts| match parts.len() {
-            0 => Ok(Cow::Borrowed("")),
-            1 => parts.into_iter().next().unwrap(),
-            _ => {
-                let parts: Result<Vec<_>, _> = parts.into_iter().collect();
-                let parts = parts?;
-                let mut string = String::with_capacity(parts.iter().map(|s| s.len()).sum());
-                for part in parts {
-                    string.push_str(&part);
-                }
-                Ok(Cow::Owned(string))
-            }
-        })
+        if self.remaining() == Some(0) {
+            return None;
+        }
+        let output = self.get(self.front, false)?;
+        self.front += 1;
+        Some(output)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining().map(usize::try_from) {
+            Some(Ok(remaining)) => (remaining, Some(remaining)),
+            _ => (usize::MAX, None),
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.skip_to(self.front.saturating_add(n as u128));
+        self.next()
+    }
+
+    fn last(mut self) -> Option<Self::Item> {
+        self.next_back()
+    }
+}
+
+impl DoubleEndedIterator for ExpressionIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.remaining() == Some(0) {
+            return None;
+        }
+        let output = self.get(self.back, true)?;
+        self.back += 1;
+        Some(output)
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let n = n as u128;
+        self.back = match self.remaining() {
+            Some(remaining) => self.back + n.min(remaining),
+            None => self.back.saturating_add(n),
+        };
+        self.next_back()
     }
 }
 
+/// A single part of an [`Expression`].
 #[derive(Clone, Debug)]
-enum Part<'a> {
+pub enum Part<'a> {
+    /// A plain string, without any escapes.
     Plain(Cow<'a, str>),
+    /// A list of alternatives.
     List(List<'a>),
+    /// A numeric or character sequence.
     Sequence(Sequence),
+    /// A nested expression, which is how non-empty list items are stored.
     Expression(Expression<'a>),
 }
 
 impl<'a> Part<'a> {
+    /// The number of values the part produces, or `None` if that overflows a
+    /// `u128`.
+    pub fn count(&self) -> Option<u128> {
+        match self {
+            Part::Plain(_) => Some(1),
+            Part::List(part) => part.count(),
+            Part::Sequence(part) => Some(part.count()),
+            Part::Expression(part) => part.count(),
+        }
+    }
+
     fn into_owned(self) -> Part<'static> {
         match self {
             Part::Plain(part) => Part::Plain(Cow::Owned(part.into_owned())),
@@ -230,64 +821,53 @@ impl<'a> Part<'a> {
     }
 }
 
-#[derive(Clone, Debug)]
-enum PartIterator<'a> {
-    Plain(iter::Once<Cow<'a, str>>),
-    List(Box<<List<'a> as IntoIterator>::IntoIter>),
-    Sequence(<Sequence as IntoIterator>::IntoIter),
-    Expression(<Expression<'a> as IntoIterator>::IntoIter),
-}
-
-impl<'a> IntoIterator for Part<'a> {
-    type Item = Result<Cow<'a, str>, CharTryFromError>;
-
-    type IntoIter = PartIterator<'a>;
-
-    fn into_iter(self) -> Self::IntoIter {
+impl Part<'_> {
+    fn fmt_with(&self, f: &mut fmt::Formatter<'_>, dialect: &Dialect) -> fmt::Result {
         match self {
-            Part::Plain(part) => PartIterator::Plain(iter::once(part.clone())),
-            Part::List(part) => PartIterator::List(Box::new(part.into_iter())),
-            Part::Sequence(part) => PartIterator::Sequence(part.into_iter()),
-            Part::Expression(part) => PartIterator::Expression(part.into_iter()),
+            Self::Plain(s) => fmt_plain(f, s, dialect, false)?,
+            Self::List(l) => l.fmt_with(f, dialect)?,
+            Self::Sequence(s) => s.fmt_with(f, dialect)?,
+            Self::Expression(e) => e.fmt_with(f, dialect)?,
         }
+        Ok(())
     }
 }
 
-impl<'a> Iterator for PartIterator<'a> {
-    type Item = Result<Cow<'a, str>, CharTryFromError>;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        match self {
-            PartIterator::Plain(part) => part.next().map(|s| Ok(s)),
-            PartIterator::List(part) => part.next(),
-            PartIterator::Sequence(part) => part.next().map(|r| r.map(|s| Cow::Owned(s))),
-            PartIterator::Expression(part) => part.next(),
+/// Write plain text, escaping any special characters, and range operators
+/// too if `range` is set.
+fn fmt_plain(
+    f: &mut fmt::Formatter<'_>,
+    plain: &str,
+    dialect: &Dialect,
+    range: bool,
+) -> fmt::Result {
+    let operator = dialect.range_operator();
+    let mut rest = plain;
+    while let Some(c) = rest.chars().next() {
+        if range && rest.starts_with(operator) {
+            write!(f, "{}{operator}", dialect.escape())?;
+            rest = &rest[operator.len()..];
+            continue;
+        }
+        if dialect.is_special(c) {
+            write!(f, "{}", dialect.escape())?;
         }
+        write!(f, "{c}")?;
+        rest = &rest[c.len_utf8()..];
     }
+    Ok(())
 }
 
-impl std::fmt::Display for Part<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::Plain(s) => {
-                for c in s.chars() {
-                    if ",{}\\".contains(c) {
-                        f.write_str("\\")?;
-                    }
-                    write!(f, "{c}")?;
-                }
-            }
-            Self::List(l) => write!(f, "{l}")?,
-            Self::Sequence(s) => write!(f, "{s}")?,
-            Self::Expression(e) => write!(f, "{e}")?,
-        }
-        Ok(())
+impl fmt::Display for Part<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_with(f, &DEFAULT_DIALECT)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
 
     #[test]
     fn test_simple_list() {
@@ -405,4 +985,1033 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_escape_any_character() {
+        let expression: Expression = r"\n{a\.b,\=}".try_into().unwrap();
+        let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+        let expected = vec!["na.b", "n="];
+        assert_eq!(generated.unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let strict = Dialect::builder().strict_strides(true).build().unwrap();
+        let test_cases = [
+            ("a{b,c", ParseErrorKind::UnclosedBrace, 1..2, 1..2),
+            ("a{b{c,d}", ParseErrorKind::UnclosedBrace, 1..2, 1..2),
+            ("ä{b,c}}d", ParseErrorKind::UnexpectedCloseBrace, 7..8, 6..7),
+            (r"a{b,c\", ParseErrorKind::TrailingEscape, 5..6, 5..6),
+            ("{1..5..x}", ParseErrorKind::InvalidStride, 7..8, 7..8),
+            ("{a..e..-2}", ParseErrorKind::InvalidStride, 7..9, 7..9),
+            ("{1..3..}", ParseErrorKind::InvalidStride, 7..7, 7..7),
+            ("{a..b..3", ParseErrorKind::UnclosedBrace, 0..1, 0..1),
+            ("{1..5..x{a,b}", ParseErrorKind::UnclosedBrace, 0..1, 0..1),
+        ];
+        for (input, kind, span, char_span) in test_cases {
+            let error = Expression::parse_with(input, &strict).unwrap_err();
+            assert_eq!(error.kind(), kind, "{input}");
+            assert_eq!(error.span(), span, "{input}");
+            assert_eq!(error.char_span(), char_span, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_invalid_stride() {
+        let test_cases: &[(&str, &[&str])] = &[
+            ("{1..5..x}", &["1..5..x"]),
+            ("{a..e..-2}", &["a..e..-2"]),
+            ("{1..3..}", &["1..3.."]),
+            ("{a,{1..5..x}}", &["a", "1..5..x"]),
+            ("{1..5..x,y}", &["1..5..x", "y"]),
+        ];
+        let strict = Dialect::builder().strict_strides(true).build().unwrap();
+        for (input, expected) in test_cases {
+            let expression = Expression::try_from(*input).unwrap();
+            let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+            assert_eq!(&generated.unwrap(), expected, "{input}");
+        }
+        let expression = Expression::parse_with("{1..5..x,y}", &strict).unwrap();
+        assert_eq!(expression.iter().count(), 2);
+    }
+
+    #[test]
+    fn test_parse_error_display() {
+        let strict = Dialect::builder().strict_strides(true).build().unwrap();
+        let error = Expression::parse_with("a\n{b,c}{1..5..xy}", &strict).unwrap_err();
+        assert_eq!(error.line_number(), 2);
+        assert_eq!(error.column(), 13);
+        assert_eq!(
+            error.to_string(),
+            "invalid sequence stride, expected a non-negative integer at line 2, column 13:\n\
+             {b,c}{1..5..xy}\n            ^^",
+        );
+    }
+
+    #[test]
+    fn test_expansion_error() {
+        let input = "x{a,b}{\u{D000}..\u{E000}..2048}";
+        let expression: Expression = input.try_into().unwrap();
+        let generated: Vec<_> = expression.into_iter().collect();
+        assert_eq!(generated.len(), 6);
+        let errors: Vec<_> = generated.into_iter().filter_map(Result::err).collect();
+        assert_eq!(errors.len(), 2);
+        for (error, index) in errors.iter().zip([1, 4]) {
+            assert_eq!(error.index(), index);
+            assert_eq!(error.codepoint(), 0xD800);
+            assert_eq!(error.span(), Some(6..input.len()));
+            assert_eq!(error.sequence(), "{\u{D000}..\u{E000}..2048}");
+        }
+
+        // The sequence is shown in the dialect it was written in, and nested
+        // parts made by a builder use the dialect of the whole expression.
+        let dialect = Dialect::builder()
+            .delimiters('[', ']')
+            .separator('|')
+            .range_operator("-")
+            .build()
+            .unwrap();
+        let input = "x[\u{D000}-\u{E000}-2048]";
+        let expression = Expression::parse_with(input, &dialect).unwrap();
+        let error = expression.iter().find_map(Result::err).unwrap();
+        assert_eq!(error.sequence(), "[\u{D000}-\u{E000}-2048]");
+        let expression = Expression::builder()
+            .list_of([Expression::builder()
+                .char_range('\u{D000}', '\u{E000}')
+                .stride(2048)
+                .build()])
+            .dialect(dialect)
+            .build();
+        let error = expression.iter().find_map(Result::err).unwrap();
+        assert_eq!(error.sequence(), "[\u{D000}-\u{E000}-2048]");
+    }
+
+    #[test]
+    fn test_bash_dialect() {
+        let test_cases: &[(&str, &[&str])] = &[
+            ("a{b,c}d}e", &["abd}e", "acd}e"]),
+            ("a{b{c,d}e", &["a{bce", "a{bde"]),
+            ("a{b}c{}d", &["a{b}c{}d"]),
+            ("{a,b{c}", &["{a,b{c}"]),
+            ("x{a{b,c}}", &["x{ab}", "x{ac}"]),
+            ("${var}{a,b}", &["${var}a", "${var}b"]),
+            (r"${a,b\}}{c,d}", &[r"${a,b\}}c", r"${a,b\}}d"]),
+            ("{001..003}", &["001", "002", "003"]),
+            ("{1..010..3}", &["001", "004", "007", "010"]),
+            ("{-01..1}", &["-01", "000", "001"]),
+            ("{10..1..-2}", &["10", "8", "6", "4", "2"]),
+            ("{e..a..-2}", &["e", "c", "a"]),
+            (
+                "{X..b}",
+                &["X", "Y", "Z", "[", "\\", "]", "^", "_", "`", "a", "b"],
+            ),
+            ("{1..5..x}", &["{1..5..x}"]),
+            ("{1..3..}", &["{1..3..}"]),
+            ("{a..e..-x}", &["{a..e..-x}"]),
+            ("{a..5}", &["{a..5}"]),
+            ("{=1..3}", &["{=1..3}"]),
+        ];
+        for (input, expected) in test_cases {
+            let expression = Expression::parse_with(input, &Dialect::bash()).unwrap();
+            let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+            assert_eq!(&generated.unwrap(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_bash_dialect_unclosed_run() {
+        let input = format!("{}a", "{".repeat(64));
+        let expression = Expression::parse_with(&input, &Dialect::bash()).unwrap();
+        let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+        assert_eq!(generated.unwrap(), vec![input.as_str()]);
+    }
+
+    #[test]
+    fn test_custom_dialect() {
+        let dialect = Dialect::builder()
+            .delimiters('[', ']')
+            .separator('|')
+            .range_operator("-")
+            .escape('^')
+            .build()
+            .unwrap();
+        let test_cases: &[(&str, &[&str])] = &[
+            (
+                "host-[a|b][1-2]",
+                &["host-a1", "host-a2", "host-b1", "host-b2"],
+            ),
+            (
+                r"C:\Users\[alice|bob]\{x,y}",
+                &[r"C:\Users\alice\{x,y}", r"C:\Users\bob\{x,y}"],
+            ),
+            ("[a^|b|c^]]", &["a|b", "c]"]),
+            (
+                "[x-z-2][=-1-1]",
+                &["x-1", "x00", "x01", "z-1", "z00", "z01"],
+            ),
+            ("[^--/]", &["-", ".", "/"]),
+        ];
+        for (input, expected) in test_cases {
+            let expression = Expression::parse_with(input, &dialect).unwrap();
+            assert_eq!(expression.dialect(), &dialect);
+            assert_eq!(&expression.to_string(), input);
+            let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+            assert_eq!(&generated.unwrap(), expected, "{input}");
+        }
+        assert_eq!(
+            Expression::parse_with("[a|b", &dialect).unwrap_err().kind(),
+            ParseErrorKind::UnclosedBrace,
+        );
+    }
+
+    #[test]
+    fn test_invalid_dialect() {
+        assert_eq!(
+            Dialect::builder().separator('{').build().unwrap_err(),
+            DialectError::DuplicateCharacter('{'),
+        );
+        assert_eq!(
+            Dialect::builder().range_operator(":1").build().unwrap_err(),
+            DialectError::InvalidRangeOperator(":1".into()),
+        );
+        assert_eq!(
+            Dialect::builder().range_operator("").build().unwrap_err(),
+            DialectError::InvalidRangeOperator("".into()),
+        );
+    }
+
+    #[test]
+    fn test_display_dialect() {
+        let bash = Dialect::bash();
+        for input in ["{001..010..3}", "{a,b}{-01..001}", "{Z..a..2}"] {
+            let expression = Expression::parse_with(input, &bash).unwrap();
+            assert_eq!(expression.to_string(), input);
+        }
+        let expression: Expression = r"{\1..\5}".try_into().unwrap();
+        assert_eq!(expression.to_string(), r"{\1..5}");
+        let reparsed: Expression = r"{\1..5}".try_into().unwrap();
+        let generated: Result<Vec<_>, _> = reparsed.into_iter().collect();
+        assert_eq!(generated.unwrap(), vec!["1", "2", "3", "4", "5"]);
+
+        // Escaped range operators stay escaped, so these are still plain.
+        let custom = Dialect::builder()
+            .delimiters('[', ']')
+            .separator('|')
+            .range_operator("-")
+            .escape('^')
+            .build()
+            .unwrap();
+        let test_cases: [(&str, &Dialect, &str); 4] = [
+            (r"{a\..c}", &DEFAULT_DIALECT, "a..c"),
+            (r"{1\..5}", &DEFAULT_DIALECT, "1..5"),
+            ("[a^-c]", &custom, "a-c"),
+            ("[1^-5]", &custom, "1-5"),
+        ];
+        for (input, dialect, output) in test_cases {
+            let expression = Expression::parse_with(input, dialect).unwrap();
+            assert_eq!(expression.to_string(), input);
+            let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+            assert_eq!(generated.unwrap(), vec![output], "{input}");
+        }
+    }
+
+    #[test]
+    fn test_count() {
+        let test_cases = [
+            ("", Some(0)),
+            ("abc", Some(1)),
+            ("{}", Some(1)),
+            ("a{b,c{d,e,},{1..10..3}}", Some(8)),
+            ("{a..z}{0..9}", Some(260)),
+            ("{\u{D7FF}..\u{E000}}", Some(0x802)),
+            (
+                "{-9223372036854775808..9223372036854775807}",
+                Some(1 << 64),
+            ),
+            (
+                "{-9223372036854775808..9223372036854775807}{1..2}",
+                Some(1 << 65),
+            ),
+            (
+                "{-9223372036854775808..9223372036854775807}{1..9223372036854775807}",
+                Some(0x7fff_ffff_ffff_ffff << 64),
+            ),
+            (
+                "{-9223372036854775808..9223372036854775807}{1..65536}{1..65536}{1..65536}{1..65536}",
+                None,
+            ),
+        ];
+        for (expression, expected) in test_cases {
+            let expression = Expression::try_from(expression).unwrap();
+            assert_eq!(expression.count(), expected, "{expression}");
+        }
+    }
+
+    #[test]
+    fn test_size_hint() {
+        let expression = Expression::try_from("{a,b{1..3}}{x,y}").unwrap();
+        let mut iter = expression.into_iter();
+        for remaining in (0..=8).rev() {
+            assert_eq!(iter.size_hint(), (remaining, Some(remaining)));
+            assert_eq!(iter.remaining(), Some(remaining as u128));
+            assert_eq!(iter.next().is_some(), remaining > 0);
+        }
+        let iter = Expression::try_from("{1..4294967296}{1..4294967296}")
+            .unwrap()
+            .into_iter();
+        assert_eq!(iter.size_hint(), (usize::MAX, None));
+        assert_eq!(iter.remaining(), Some(1 << 64));
+    }
+
+    #[test]
+    fn test_nth_expansion() {
+        for input in [
+            "",
+            "abc",
+            "a{b,c{d,e,},{1..10..3}}x{z..x}",
+            "{}{,}{a{,b{1..2}}c,d}{-2..2}",
+        ] {
+            let expression = Expression::try_from(input).unwrap();
+            let expected: Vec<_> = expression.clone().into_iter().collect();
+            for (n, output) in expected.iter().enumerate() {
+                assert_eq!(expression.nth_expansion(n as u128).as_ref(), Some(output));
+            }
+            assert_eq!(expression.nth_expansion(expected.len() as u128), None);
+        }
+        let expression = Expression::try_from(
+            "{a..c}{-9223372036854775808..9223372036854775807}{-9223372036854775808..9223372036854775807}",
+        )
+        .unwrap();
+        assert_eq!(expression.count(), None);
+        assert_eq!(
+            expression.nth_expansion(u128::MAX).unwrap().unwrap(),
+            "a92233720368547758079223372036854775807",
+        );
+    }
+
+    #[test]
+    fn test_skip_to() {
+        let expression = Expression::try_from("{a..c}{1..3}").unwrap();
+        let mut iter = expression.into_iter();
+        iter.skip_to(4);
+        assert_eq!(iter.next().unwrap().unwrap(), "b2");
+        assert_eq!(iter.nth(1).unwrap().unwrap(), "c1");
+        iter.skip_to(0);
+        assert_eq!(iter.next().unwrap().unwrap(), "a1");
+        assert_eq!(iter.remaining(), Some(8));
+        iter.skip_to(100);
+        assert_eq!(iter.remaining(), Some(0));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn test_position_of() {
+        for input in [
+            "a{b,c{d,e,},{1..10..3}}x{z..x}",
+            "{1..20}{1..5}",
+            "{=-10..10..3}{,-}{1..3}",
+            "{a,b}{,b}{b,}",
+        ] {
+            let expression = Expression::try_from(input).unwrap();
+            let outputs: Vec<_> = expression.clone().into_iter().map(Result::unwrap).collect();
+            for output in &outputs {
+                let expected: Vec<_> = (0..outputs.len() as u128)
+                    .filter(|&n| outputs[n as usize] == *output)
+                    .collect();
+                assert_eq!(
+                    expression.positions_of(output, usize::MAX),
+                    expected,
+                    "{output}"
+                );
+                assert_eq!(
+                    expression.positions_of(output, 1),
+                    expected[..1],
+                    "{output}"
+                );
+                assert_eq!(expression.position_of(output), Some(expected[0]));
+            }
+        }
+        // There are 2^50 ways to pick each `{a,a}` and C(50, 25) ways to pick
+        // the `{,a}`, which can't be enumerated. The first match takes the
+        // first item of every list except the last 25 `{,a}`.
+        let input = "{a,a}{,a}".repeat(50);
+        let expression = Expression::try_from(input.as_str()).unwrap();
+        let output = "a".repeat(75);
+        assert_eq!(
+            expression.position_of(&output),
+            Some((4u128.pow(25) - 1) / 3)
+        );
+        let input = "{a,a}{,a}".repeat(5);
+        let small = Expression::try_from(input.as_str()).unwrap();
+        let outputs: Vec<_> = small.iter().map(Result::unwrap).collect();
+        let expected: Vec<_> = (0..outputs.len() as u128)
+            .filter(|&n| outputs[n as usize] == "aaaaaaa")
+            .take(10)
+            .collect();
+        assert_eq!(small.positions_of("aaaaaaa", 10), expected);
+        let expression = Expression::try_from("host{=1..100}.{us,eu}").unwrap();
+        assert_eq!(expression.position_of("host050.eu"), Some(99));
+        assert_eq!(expression.position_of("host50.eu"), None);
+        assert_eq!(expression.position_of("host101.eu"), None);
+        assert_eq!(expression.position_of("host050.eu."), None);
+        let expression = Expression::try_from(
+            "{a..c}{-9223372036854775808..9223372036854775807}{-9223372036854775808..9223372036854775807}",
+        )
+        .unwrap();
+        assert_eq!(
+            expression.position_of("a92233720368547758079223372036854775807"),
+            Some(u128::MAX),
+        );
+        assert_eq!(expression.position_of("b00"), None);
+        assert_eq!(expression.position_of("b0-1"), None);
+    }
+
+    #[test]
+    fn test_reverse() {
+        for input in [
+            "",
+            "a{b,c{d,e,},{1..10..3}}x{z..x}",
+            "{}{,}{a{,b{1..2}}c,d}{-2..2}",
+        ] {
+            let expression = Expression::try_from(input).unwrap();
+            let mut expected: Vec<_> = expression.clone().into_iter().collect();
+            expected.reverse();
+            let reversed: Vec<_> = expression.clone().into_iter().rev().collect();
+            assert_eq!(reversed, expected);
+            assert_eq!(expression.into_iter().last(), expected.first().cloned());
+        }
+
+        let expression = Expression::try_from("{a..c}{1..3}").unwrap();
+        let mut iter = expression.into_iter();
+        assert_eq!(iter.next_back().unwrap().unwrap(), "c3");
+        assert_eq!(iter.nth_back(2).unwrap().unwrap(), "b3");
+        assert_eq!(iter.next().unwrap().unwrap(), "a1");
+        assert_eq!(iter.remaining(), Some(4));
+        iter.skip_to(7);
+        assert_eq!(iter.next(), None);
+        iter.skip_to(4);
+        let rest: Vec<_> = iter.map(Result::unwrap).collect();
+        assert_eq!(rest, ["b2"]);
+
+        let input = "x{a,b}{\u{D000}..\u{E000}..2048}";
+        let expression: Expression = input.try_into().unwrap();
+        let errors: Vec<_> = expression
+            .into_iter()
+            .rev()
+            .filter_map(Result::err)
+            .map(|error| error.index())
+            .collect();
+        assert_eq!(errors, [4, 1]);
+
+        let expression = Expression::try_from(
+            "{a..c}{-9223372036854775808..9223372036854775807}{-9223372036854775808..9223372036854775807}",
+        )
+        .unwrap();
+        assert_eq!(
+            expression.into_iter().next_back().unwrap().unwrap(),
+            "c92233720368547758079223372036854775807",
+        );
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_sample() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+        use std::collections::HashSet;
+
+        let expression = Expression::try_from("{a..z}{=1..100}{x,y,}").unwrap();
+        let outputs: HashSet<_> = expression.clone().into_iter().map(Result::unwrap).collect();
+        let sample: Vec<_> = expression
+            .sample(StdRng::seed_from_u64(1), 1000)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(sample.len(), 1000);
+        assert!(sample.iter().all(|output| outputs.contains(output)));
+        let again: Vec<_> = expression
+            .sample(StdRng::seed_from_u64(1), 1000)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(sample, again);
+
+        let mut rng = StdRng::seed_from_u64(2);
+        let sample: Vec<_> = expression
+            .sample_distinct(&mut rng, 5000)
+            .map(Result::unwrap)
+            .collect();
+        let distinct: HashSet<_> = sample.iter().cloned().collect();
+        assert_eq!(sample.len(), 5000);
+        assert_eq!(distinct.len(), 5000);
+        let all = expression.sample_distinct(&mut rng, 10000);
+        assert_eq!(all.len(), outputs.len());
+        let all: HashSet<_> = all.map(Result::unwrap).collect();
+        assert_eq!(all, outputs);
+
+        assert_eq!(
+            Expression::try_from("")
+                .unwrap()
+                .sample(&mut rng, 5)
+                .count(),
+            0
+        );
+        let expression = Expression::try_from(
+            "{a,b{1..2}}{-9223372036854775808..9223372036854775807}{-9223372036854775808..9223372036854775807}",
+        )
+        .unwrap();
+        assert_eq!(expression.count(), None);
+        for output in expression.sample(&mut rng, 100) {
+            let output = output.unwrap();
+            assert!(output.starts_with(['a', 'b']), "{output}");
+        }
+    }
+
+    #[test]
+    fn test_shuffled() {
+        let expression = Expression::try_from("node{=1..999}").unwrap();
+        let ordered: Vec<_> = expression.clone().into_iter().map(Result::unwrap).collect();
+        let shuffled: Vec<_> = expression.shuffled(7).map(Result::unwrap).collect();
+        assert_eq!(shuffled.len(), ordered.len());
+        assert_ne!(shuffled, ordered);
+        let mut sorted = shuffled.clone();
+        sorted.sort();
+        assert_eq!(sorted, ordered);
+
+        let again: Vec<_> = expression.shuffled(7).map(Result::unwrap).collect();
+        assert_eq!(again, shuffled);
+        let other: Vec<_> = expression.shuffled(8).map(Result::unwrap).collect();
+        assert_ne!(other, shuffled);
+
+        let expression = Expression::try_from("{a,b}").unwrap();
+        let mut iter = expression.shuffled(0);
+        assert_eq!(iter.remaining(), Some(2));
+        iter.next();
+        iter.next();
+        assert_eq!(iter.next(), None);
+        assert_eq!(Expression::try_from("").unwrap().shuffled(0).count(), 0);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_iter() {
+        use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+
+        for input in ["", "a{b,c{d,e,},{1..10..3}}x{z..x}", "{a..z}{a..z}{=0..99}"] {
+            let expression = Expression::try_from(input).unwrap();
+            let expected: Vec<_> = expression.clone().into_iter().collect();
+            assert_eq!(expression.par_iter().len(), expected.len());
+            let collected: Vec<_> = expression.par_iter().collect();
+            assert_eq!(collected, expected);
+        }
+        let input = "x{a,b}{\u{D000}..\u{E000}..2048}";
+        let expression = Expression::try_from(input).unwrap();
+        let errors: Vec<_> = expression
+            .par_iter()
+            .filter_map(Result::err)
+            .map(|error| error.index())
+            .collect();
+        assert_eq!(errors, [1, 4]);
+    }
+
+    #[test]
+    fn test_shard() {
+        let expression = Expression::try_from("{a..c}{1..7}{x,y}").unwrap();
+        let expected: Vec<_> = expression.clone().into_iter().collect();
+        for n in [1, 2, 5, 42, 100] {
+            let shards: Vec<Vec<_>> = (0..n)
+                .map(|k| expression.clone().shard(k, n).collect())
+                .collect();
+            let sizes: Vec<_> = shards.iter().map(Vec::len).collect();
+            let (min, max) = (sizes.iter().min().unwrap(), sizes.iter().max().unwrap());
+            assert!(max - min <= 1, "{sizes:?}");
+            assert_eq!(shards.concat(), expected);
+        }
+        let mut shard = expression.shard(1, 3);
+        assert_eq!(shard.remaining(), Some(14));
+        assert_eq!(shard.next().unwrap().unwrap(), "b1x");
+        assert_eq!(shard.next_back().unwrap().unwrap(), "b7y");
+    }
+
+    #[test]
+    fn test_cursor() {
+        let expression = Expression::try_from("{a..c}{1..7}{x,y}").unwrap();
+        let expected: Vec<_> = expression.clone().into_iter().collect();
+        let mut iter = expression.clone().shard(1, 3);
+        let mut resumed: Vec<_> = iter.by_ref().take(5).collect();
+        iter.next_back();
+        let cursor: Cursor = iter.cursor().to_string().parse().unwrap();
+        assert_eq!(cursor.index(), 19);
+        resumed.extend(expression.clone().iter_from(cursor).unwrap());
+        assert_eq!(resumed, expected[14..27]);
+
+        let same = Expression::builder()
+            .char_range('a', 'c')
+            .int_range(1, 7)
+            .list(["x", "y"])
+            .build();
+        assert_eq!(same.fingerprint(), expression.fingerprint());
+        // Both display as `{=1..10}`, but only one pads to two digits.
+        let wide = Expression::builder().int_range(1, 10).width(2).build();
+        let mut narrow = wide.clone();
+        if let Part::Sequence(Sequence::Int { width, .. }) = &mut narrow.parts_mut()[0] {
+            *width = Some(1);
+        }
+        assert_eq!(narrow.to_string(), wide.to_string());
+        assert_ne!(narrow.fingerprint(), wide.fingerprint());
+        let other = Expression::try_from("{a..c}{1..7}{x,z}").unwrap();
+        assert_eq!(
+            other.iter_from(cursor).unwrap_err(),
+            CursorError::FingerprintMismatch {
+                expected: Expression::try_from("{a..c}{1..7}{x,z}")
+                    .unwrap()
+                    .fingerprint(),
+                found: expression.fingerprint(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_expand_each() {
+        for input in [
+            "",
+            "abc",
+            "a{b,c{d,e,},{1..10..3}}x{z..x}",
+            "{}{,}{a{,b{1..2}}c,d}{-2..2}",
+            "{a..c}{=8..10}{x,}",
+        ] {
+            let expression = Expression::try_from(input).unwrap();
+            let expected: Vec<_> = expression.clone().into_iter().map(Result::unwrap).collect();
+            let mut outputs = Vec::new();
+            expression
+                .expand_each(|output| outputs.push(output.to_owned()))
+                .unwrap();
+            assert_eq!(outputs, expected);
+
+            let mut written = Vec::new();
+            expression.write_all(&mut written, "\n").unwrap();
+            assert_eq!(String::from_utf8(written).unwrap(), expected.join("\n"));
+        }
+
+        let input = "x{a,b}{\u{D000}..\u{E000}..2048}";
+        let expression = Expression::try_from(input).unwrap();
+        let mut outputs = Vec::new();
+        let error = expression
+            .expand_each(|output| outputs.push(output.to_owned()))
+            .unwrap_err();
+        assert_eq!(outputs, ["xa\u{D000}"]);
+        assert_eq!(error.index(), 1);
+        let error = expression.write_all(&mut Vec::new(), "\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_iter() {
+        let templates: HashMap<&str, Expression<'static>> = [
+            ("hosts", "{alpha,beta}.example.com".parse().unwrap()),
+            ("nodes", "node{=1..3}".parse().unwrap()),
+        ]
+        .into_iter()
+        .collect();
+        for _ in 0..2 {
+            let hosts: Vec<_> = templates["hosts"].iter().map(Result::unwrap).collect();
+            assert_eq!(hosts, ["alpha.example.com", "beta.example.com"]);
+            let nodes: Vec<_> = (&templates["nodes"]).into_iter().rev().collect();
+            assert_eq!(nodes.len(), 3);
+            assert_eq!(nodes[0].as_deref(), Ok("node3"));
+        }
+
+        let expression: Expression = "{alpha,beta}".parse().unwrap();
+        let Part::List(list) = &expression.parts()[0] else {
+            panic!("expected a list");
+        };
+        let Part::Expression(item) = &list.items()[0] else {
+            panic!("expected an expression");
+        };
+        let Part::Plain(alpha) = &item.parts()[0] else {
+            panic!("expected a plain part");
+        };
+        match expression.iter().next().unwrap().unwrap() {
+            Cow::Borrowed(output) => assert!(std::ptr::eq(output, alpha.as_ref())),
+            Cow::Owned(_) => panic!("output should be borrowed"),
+        }
+    }
+
+    #[test]
+    fn test_unique() {
+        let test_cases = [
+            "{a,,a}",
+            "{a,ab}{bc,c}",
+            "{a..c}{x,,x}{=1..2}",
+            "{=1..20}{1,11,,1}{1..12}",
+            "{1..12}{1..12}",
+            "{{a,b},{b,c}}{x,y}",
+            "x{a..e}y{1..3}",
+            "{}",
+        ];
+        for input in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let mut seen = std::collections::HashSet::new();
+            let expected: Vec<_> = expression
+                .iter()
+                .map(Result::unwrap)
+                .filter(|output| seen.insert(output.clone()))
+                .collect();
+            let outputs: Vec<_> = expression.iter().unique().map(Result::unwrap).collect();
+            assert_eq!(outputs, expected, "{input}");
+        }
+
+        // Repeats are only remembered within runs of the leading parts.
+        let expression = Expression::try_from("{a..z}{=1..99999}{,a,a}").unwrap();
+        let mut unique = expression.iter().unique();
+        assert_eq!(unique.nth(3).unwrap().unwrap(), "a00002a");
+        unique.nth(2 * 99999 - 5);
+        assert_eq!(unique.next().unwrap().unwrap(), "b00001");
+    }
+
+    #[test]
+    fn test_sorted() {
+        let test_cases = [
+            "{=1..99}-{b,a}",
+            "{1..99}-{b,a}",
+            "{1..99}{b,a}",
+            "{1..30}{1,2}",
+            "x{1..12}",
+            "x{12..1..2}y",
+            "a{1..-1..2}",
+            "{O..i}{=-2..17..2}",
+            "a{=4..-5..3}{f..A}",
+            "{1..3}{}{4..5}",
+            "host{z..a}.{x,y,xy}",
+            "host{z..a}.{x,xy}-",
+            "{a,ab}{c,d}",
+            "{-12..12..5}{x,}{a..c}",
+            "{=-5..5}{=8..12}",
+            "v{{1..3}.{0..12},{a..c}{9,10}}",
+            "{}",
+        ];
+        for input in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            for order in [Order::Lexicographic, Order::Natural] {
+                let mut expected: Vec<_> = expression.iter().map(Result::unwrap).collect();
+                expected.sort_by(|a, b| order.compare(a, b));
+                let outputs: Vec<_> = expression.sorted(order).map(Result::unwrap).collect();
+                assert_eq!(outputs, expected, "{input} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let test_cases = [
+            "web{=1..40}.{us,eu}-prod",
+            "{a,ab}{bc,c}",
+            "{-10..10..3}{,x}",
+            "{z..a..2}{}",
+            "{a,{b,}{c..e}}",
+            "{}",
+            "a{}",
+            "{{},x}",
+        ];
+        for input in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let outputs: std::collections::HashSet<_> =
+                expression.iter().map(Result::unwrap).collect();
+            for output in &outputs {
+                assert!(expression.matches(output), "{input}: {output}");
+            }
+            // Every output with a char changed, added or removed.
+            for output in &outputs {
+                for i in 0..=output.len() {
+                    for c in ['a', 'x', '0', '1', '-'] {
+                        let mut candidate = output.to_string();
+                        candidate.insert(i, c);
+                        assert_eq!(
+                            expression.matches(&candidate),
+                            outputs.contains(candidate.as_str()),
+                            "{input}: {candidate}"
+                        );
+                    }
+                }
+            }
+            assert_eq!(expression.matches(""), outputs.contains(""), "{input}");
+        }
+
+        let expression = Expression::try_from("node{1..1000000000..7}{a,a}{a,a}").unwrap();
+        assert!(expression.matches("node999999995aa"));
+        assert!(!expression.matches("node999999994aa"));
+        assert!(!expression.matches("node0999999995aa"));
+        let input = "{a,a}".repeat(64);
+        let expression = Expression::try_from(input.as_str()).unwrap();
+        assert!(expression.matches(&"a".repeat(64)));
+        assert!(!expression.matches(&"a".repeat(65)));
+    }
+
+    #[test]
+    fn test_to_regex_string() {
+        let test_cases = [
+            "web{=1..40}.{us,eu}-prod",
+            "{a,ab}{bc,c}",
+            "{-10..10..3}{,x}",
+            "{=-120..7}",
+            "{=0..0100}",
+            "{-9223372036854775808..-9223372036854775800}",
+            "{z..a..2}{}",
+            r"{a,{b,}{\\..^}}",
+            "x{]..^}{-..-}",
+            "{}",
+            "a{{},.}",
+            "(a+b)*[c]?",
+        ];
+        for input in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let pattern = format!("^(?:{})$", expression.to_regex_string());
+            let regex = ::regex::Regex::new(&pattern).unwrap();
+            let outputs: std::collections::HashSet<_> =
+                expression.iter().filter_map(Result::ok).collect();
+            for output in &outputs {
+                assert!(regex.is_match(output), "{input}: {pattern} {output}");
+            }
+            for output in &outputs {
+                for i in 0..=output.len() {
+                    for c in ['a', 'x', '0', '1', '-', '.', ']'] {
+                        let mut candidate = output.to_string();
+                        if !candidate.is_char_boundary(i) {
+                            continue;
+                        }
+                        candidate.insert(i, c);
+                        assert_eq!(
+                            regex.is_match(&candidate),
+                            outputs.contains(candidate.as_str()),
+                            "{input}: {pattern} {candidate}"
+                        );
+                    }
+                }
+            }
+        }
+
+        // Numeric ranges are checked against every number near them.
+        for input in ["{=-1234..5678}", "{-99..1001}", "{=7..-13}", "{0..0}"] {
+            let expression = Expression::try_from(input).unwrap();
+            let pattern = format!("^(?:{})$", expression.to_regex_string());
+            let regex = ::regex::Regex::new(&pattern).unwrap();
+            for number in -2000..7000 {
+                for candidate in [number.to_string(), format!("{number:05}")] {
+                    assert_eq!(
+                        regex.is_match(&candidate),
+                        expression.matches(&candidate),
+                        "{input}: {pattern} {candidate}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_glob() {
+        let test_cases = [
+            ("log-{=1..31}.txt", "log-[0-3][0-9].txt", false),
+            ("{a,b}{1..3}", "[ab][1-3]", true),
+            ("host{1..12}.example", "host[1-9]*.example", false),
+            (
+                "{x,y}{ab,cd}{1,2}.{txt,log}",
+                "[xy][ac][bd][12].[lt][ox][gt]",
+                false,
+            ),
+            ("{ax,bx,ay,by}-{0..9}", "[ab][xy]-[0-9]", true),
+            ("{=0..999999}", "[0-9][0-9][0-9][0-9][0-9][0-9]", true),
+            ("{=1..1000000}", "[01][0-9][0-9][0-9][0-9][0-9][0-9]", false),
+            ("{1..1000000}{a,b}", "[0-9]*[ab]", false),
+            ("a*b?{[,],!}\\{\\}", "a\\*b\\?[]![]\\{\\}", true),
+            (r"{\\..^}", "?", false),
+            ("{a,{b,c}}{}", "[a-c]", true),
+            ("{}", "", true),
+            ("", "", false),
+        ];
+        for (input, pattern, exact) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let glob = expression.to_glob();
+            assert_eq!(
+                (glob.pattern(), glob.is_exact()),
+                (pattern, exact),
+                "{input}"
+            );
+        }
+
+        for (input, _, _) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let glob = expression.to_glob();
+            let matcher = globset::GlobBuilder::new(glob.pattern())
+                .backslash_escape(true)
+                .build()
+                .unwrap()
+                .compile_matcher();
+            let outputs: std::collections::HashSet<_> = expression
+                .iter()
+                .take(100000)
+                .filter_map(Result::ok)
+                .collect();
+            for output in &outputs {
+                assert!(
+                    matcher.is_match(output.as_ref()),
+                    "{input}: {glob} {output}"
+                );
+            }
+            if !glob.is_exact() || outputs.len() > 1000 {
+                continue;
+            }
+            for output in &outputs {
+                for i in 0..=output.len() {
+                    for c in ['a', 'x', '0', '1', '-', '.', ']', '!'] {
+                        let mut candidate = output.to_string();
+                        candidate.insert(i, c);
+                        assert_eq!(
+                            matcher.is_match(&candidate),
+                            outputs.contains(candidate.as_str()),
+                            "{input}: {glob} {candidate}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_simplify() {
+        let test_cases = [
+            ("{a,{b,d}}", "{a,b,d}"),
+            ("{a,{b,c}}", "{a..c}"),
+            ("x{{a}}y", "xay"),
+            ("a{}b{,}", "ab{,}"),
+            ("{}", "{}"),
+            ("", ""),
+            ("{1,2,3}", "{1..3}"),
+            ("{1,{2,3},{4..6}}", "{1..6}"),
+            ("{{1..3},{4..6}}", "{1..6}"),
+            ("{1,2,x,4,6,8}", "{1,2,x,{4..8..2}}"),
+            ("{c,b,a}", "{c..a}"),
+            ("{08,09,{10..12}}", "{=8..12}"),
+            ("{7,08,09}", "{7,08,09}"),
+            ("{5..5}{=3..3}", "53"),
+            ("{a,{b,}{c,d}}", "{a,{b,}{c,d}}"),
+            ("{b..d}{a,{1..2}}", "{b..d}{a,{1..2}}"),
+        ];
+        for (input, expected) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let simplified = expression.simplify();
+            assert_eq!(simplified.to_string(), expected, "{input}");
+            assert!(
+                expression
+                    .iter()
+                    .map(Result::unwrap)
+                    .eq(simplified.iter().map(Result::unwrap)),
+                "{input}"
+            );
+            // It displays as an expression that parses the same.
+            assert_eq!(
+                Expression::try_from(expected)
+                    .unwrap()
+                    .simplify()
+                    .to_string(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        let test_cases = [
+            ("{b,a,{c,d}}", "{a..d}"),
+            ("{b,a}", "{a,b}"),
+            ("{{4..6},{1..3}}", "{1..6}"),
+            ("{3..1}", "{1..3}"),
+            ("{1..2}", "{1,2}"),
+            ("{x,{10..1..3}}", "{x,{1..10..3}}"),
+            ("{{1..3},{2..4},{4..6}}", "{{1..6},{2..4}}"),
+            ("{1,1}", "{1,1}"),
+            ("{{=8..10},7}", "{7,{=8..10}}"),
+            ("h{z,y}.{{eu,us}-{2,1}}", "h{y,z}.{eu,us}-{1,2}"),
+        ];
+        for (input, expected) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let canonical = expression.canonicalize();
+            assert_eq!(canonical.to_string(), expected, "{input}");
+            let sorted = |expression: &Expression<'_>| {
+                let mut outputs: Vec<String> = expression
+                    .iter()
+                    .map(|output| output.unwrap().into_owned())
+                    .collect();
+                outputs.sort();
+                outputs
+            };
+            assert_eq!(sorted(&canonical), sorted(&expression), "{input}");
+            assert_eq!(canonical.canonicalize().to_string(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_equivalent() {
+        use Equivalence::{Sequence, Set};
+        let test_cases = [
+            ("{a,b}{1..3}", "{a,b}{1,2,3}", Sequence, None),
+            ("{a,b}{1..3}", "{b,a}{1..3}", Sequence, Some("a1")),
+            ("{a,b}{1..3}", "{b,a}{3..1}", Set, None),
+            ("{a,b}{1..3}", "{a,b}{1..4}", Sequence, Some("b1")),
+            ("{a,b}{1..3}", "{a,b}{1..4}", Set, Some("a4")),
+            ("{a,a,b}", "{a,b}", Set, None),
+            ("{a,a,b}", "{a,b}", Sequence, Some("a")),
+            ("{a,ab}{c,bc}", "{ac,abc,abbc}", Set, None),
+            ("{a,ab}{c,bc}", "{ac,abc,abbc}", Sequence, Some("abc")),
+            ("x{1..1000000000}y", "x{1..1000000000}y", Sequence, None),
+            (
+                "x{1..1000000000}y",
+                "x{1..999999999}y",
+                Sequence,
+                Some("x1000000000y"),
+            ),
+            (
+                "x{1..1000000000}y",
+                "x{1..999999999}y",
+                Set,
+                Some("x1000000000y"),
+            ),
+            (
+                "x{1..1000000000}{y,z}",
+                "x{{1..9},{10..1000000000}}{y,z}",
+                Set,
+                None,
+            ),
+            ("{}", "", Set, Some("")),
+            ("", "{a..b}{}", Sequence, Some("a")),
+            ("", "", Sequence, None),
+        ];
+        for (a, b, mode, expected) in test_cases {
+            let (a, b) = (
+                Expression::try_from(a).unwrap(),
+                Expression::try_from(b).unwrap(),
+            );
+            let verdict = a.equivalent(&b, mode);
+            assert_eq!(verdict.counterexample(), expected, "{a} {b} {mode:?}");
+            assert_eq!(
+                verdict.is_equivalent(),
+                expected.is_none(),
+                "{a} {b} {mode:?}"
+            );
+            assert_eq!(b.equivalent(&a, mode).is_equivalent(), expected.is_none());
+        }
+        // Neither structure nor enumeration can tell these apart.
+        let a = Expression::try_from("{a..z}{a..z}{a..z}{a..z}").unwrap();
+        let b = Expression::try_from("{{a..z}{a..z}{a..z}{a..z},abcd}").unwrap();
+        assert_eq!(a.equivalent(&b, Set), Verdict::Unknown);
+        // These display the same, but only one pads to two digits.
+        let wide = Expression::builder().int_range(1, 10).width(2).build();
+        let mut narrow = wide.clone();
+        if let Part::Sequence(super::Sequence::Int { width, .. }) = &mut narrow.parts_mut()[0] {
+            *width = Some(1);
+        }
+        for mode in [Sequence, Set] {
+            let verdict = wide.equivalent(&narrow, mode);
+            assert_eq!(verdict.counterexample(), Some("01"), "{mode:?}");
+            let verdict = narrow.equivalent(&wide, mode);
+            assert_eq!(verdict.counterexample(), Some("1"), "{mode:?}");
+        }
+    }
 }
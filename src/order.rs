@@ -0,0 +1,407 @@
+use std::borrow::Cow;
+use std::cmp::Ordering;
+
+use crate::{ExpansionError, Expression, ExpressionIterator, Part, Sequence};
+
+/// Parts with at most this many values may be sorted up front, so that the
+/// outputs can be streamed in runs.
+const TABLE_LIMIT: u128 = 1 << 16;
+
+/// A string order that outputs can be sorted in, with [`Expression::sorted`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Order {
+    /// Byte order, as `str` is compared.
+    #[default]
+    Lexicographic,
+    /// Natural order, as `ls -v` sorts, where runs of digits are compared by
+    /// their numeric value so that `a2` comes before `a10`.  Strings that are
+    /// otherwise equal, like `a01` and `a1`, fall back to byte order.
+    Natural,
+}
+
+/// Split a string into alternating runs of ASCII digits and other chars.
+fn tokens(s: &str) -> impl Iterator<Item = &str> {
+    let mut rest = s;
+    std::iter::from_fn(move || {
+        let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (token, tail) = rest.split_at(end);
+        rest = tail;
+        (!token.is_empty()).then_some(token)
+    })
+}
+
+fn is_digits(token: &str) -> bool {
+    token.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Compare two runs of digits by their numeric value.
+fn cmp_numeric(a: &str, b: &str) -> Ordering {
+    let a = a.trim_start_matches('0');
+    let b = b.trim_start_matches('0');
+    a.len().cmp(&b.len()).then_with(|| a.cmp(b))
+}
+
+impl Order {
+    /// Compare two strings in this order.
+    pub fn compare(self, a: &str, b: &str) -> Ordering {
+        match self {
+            Order::Lexicographic => a.cmp(b),
+            Order::Natural => {
+                let mut a_tokens = tokens(a);
+                let mut b_tokens = tokens(b);
+                loop {
+                    let ordering = match (a_tokens.next(), b_tokens.next()) {
+                        (None, None) => return a.cmp(b),
+                        (None, Some(_)) => return Ordering::Less,
+                        (Some(_), None) => return Ordering::Greater,
+                        (Some(a), Some(b)) if is_digits(a) && is_digits(b) => cmp_numeric(a, b),
+                        (Some(a), Some(b)) => a.cmp(b),
+                    };
+                    if ordering.is_ne() {
+                        return ordering;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether `a` and `b` are ordered the same way whatever is appended to
+    /// each of them.  If `digits_closed` is set, what is appended never
+    /// starts with a digit.
+    fn decides(self, a: &str, b: &str, digits_closed: bool) -> bool {
+        if self == Order::Lexicographic {
+            return !a.starts_with(b) && !b.starts_with(a);
+        }
+        let a_tokens: Vec<_> = tokens(a).collect();
+        let b_tokens: Vec<_> = tokens(b).collect();
+        // The last token of each string may be extended by what follows.
+        let open = |tokens: &[&str], i: usize| {
+            i + 1 == tokens.len() && !(digits_closed && is_digits(tokens[i]))
+        };
+        for (i, (a_token, b_token)) in a_tokens.iter().zip(&b_tokens).enumerate() {
+            let a_open = open(&a_tokens, i);
+            let b_open = open(&b_tokens, i);
+            match (is_digits(a_token), is_digits(b_token)) {
+                (true, true) => {
+                    if a_open || b_open {
+                        return false;
+                    }
+                    if cmp_numeric(a_token, b_token).is_ne() {
+                        return true;
+                    }
+                    // Numerically equal runs tie, leaving the order to what
+                    // follows.
+                    if a_token != b_token {
+                        return false;
+                    }
+                }
+                (false, false) => {
+                    if a_token.chars().zip(b_token.chars()).any(|(a, b)| a != b) {
+                        return true;
+                    }
+                    if a_open || b_open || a_token != b_token {
+                        return false;
+                    }
+                }
+                // Runs of digits and other chars differ in their first char.
+                _ => return true,
+            }
+        }
+        false
+    }
+}
+
+/// Whether any output of a part may start with an ASCII digit.
+fn may_start_with_digit(part: &Part<'_>) -> bool {
+    match part {
+        Part::Plain(plain) => plain.chars().next().is_none_or(|c| c.is_ascii_digit()),
+        Part::Sequence(Sequence::Char { sequence, .. }) => {
+            sequence.start.min(sequence.end) <= '9' && sequence.start.max(sequence.end) >= '0'
+        }
+        Part::Sequence(Sequence::Int { .. }) => true,
+        Part::List(list) => list.items().iter().any(may_start_with_digit),
+        Part::Expression(expression) => expression.parts().first().is_none_or(may_start_with_digit),
+    }
+}
+
+/// The order of the values of one part, as the index of the value with each
+/// rank.
+#[derive(Clone, Debug)]
+enum Ranks {
+    Ascending,
+    Descending(u128),
+    Table(Vec<u128>),
+}
+
+impl Ranks {
+    fn index(&self, rank: u128) -> u128 {
+        match self {
+            Ranks::Ascending => rank,
+            Ranks::Descending(count) => count - 1 - rank,
+            Ranks::Table(table) => table[rank as usize],
+        }
+    }
+
+    /// Order the values of a part, if the values of the part decide the order
+    /// of the outputs that contain them.  `last` is whether nothing follows
+    /// the part, `digits_closed` whether what follows never starts with a
+    /// digit, and `after_text` whether something always comes before it.
+    /// Returns the ranks, whether any value ends with a digit, and whether
+    /// every value is empty.
+    fn of_part(
+        part: &Part<'_>,
+        order: Order,
+        last: bool,
+        digits_closed: bool,
+        after_text: bool,
+    ) -> Option<(Self, bool, bool)> {
+        let digits_closed = last || digits_closed;
+        if let Part::Sequence(sequence) = part {
+            // Sequences that are already in order aren't worth a table.
+            let monotonic = match sequence {
+                Sequence::Char { sequence, .. } => {
+                    let digits = may_start_with_digit(part);
+                    (order == Order::Lexicographic || !digits)
+                        .then_some((sequence.start <= sequence.end, digits))
+                }
+                Sequence::Int { width, sequence } => {
+                    let len = |number: i64| match *width {
+                        Some(width) => format!("{number:0width$}").len(),
+                        None => number.to_string().len(),
+                    };
+                    let fixed = len(sequence.start) == len(sequence.end);
+                    let decided = match order {
+                        Order::Lexicographic => fixed,
+                        Order::Natural => digits_closed,
+                    };
+                    (sequence.start.min(sequence.end) >= 0 && decided)
+                        .then_some((sequence.start <= sequence.end, true))
+                }
+            };
+            if let Some((ascending, ends_with_digit)) = monotonic {
+                let ranks = if ascending {
+                    Ranks::Ascending
+                } else {
+                    Ranks::Descending(sequence.count())
+                };
+                return Some((ranks, ends_with_digit, false));
+            }
+        }
+        if part.count()? > TABLE_LIMIT {
+            return None;
+        }
+        let expression = Expression {
+            parts: vec![part.clone()],
+            dialect: None,
+        };
+        let values = expression.iter().collect::<Result<Vec<_>, _>>().ok()?;
+        // In natural order, a value that starts with other chars continues
+        // the run of other chars before it, so compare the values as they
+        // would be after some.
+        let keys: Vec<Cow<'_, str>> = if after_text {
+            values
+                .iter()
+                .map(|value| format!(".{value}").into())
+                .collect()
+        } else {
+            values
+                .iter()
+                .map(|value| Cow::Borrowed(value.as_ref()))
+                .collect()
+        };
+        let mut table: Vec<u128> = (0..keys.len() as u128).collect();
+        table.sort_by(|&a, &b| order.compare(&keys[a as usize], &keys[b as usize]));
+        let decided = last
+            || table.windows(2).all(|pair| {
+                order.decides(
+                    &keys[pair[0] as usize],
+                    &keys[pair[1] as usize],
+                    digits_closed,
+                )
+            });
+        let ends_with_digit = values
+            .iter()
+            .any(|value| value.ends_with(|c: char| c.is_ascii_digit()));
+        let empty = values.iter().all(|value| value.is_empty());
+        decided.then_some((Ranks::Table(table), ends_with_digit, empty))
+    }
+}
+
+/// A leading part of the expression whose values are iterated in order.
+#[derive(Clone, Debug)]
+struct Digit {
+    ranks: Ranks,
+    count: u128,
+    place: u128,
+}
+
+/// Iterates over the outputs of an [`Expression`] sorted in an [`Order`],
+/// created by [`Expression::sorted`].
+///
+/// Where the values of the leading parts of the expression decide the order
+/// of the outputs, as with `{=01..99}-{a,b}` or `host{a..z}.{x,y}`, those
+/// parts are iterated in order, and only the outputs that share their values
+/// are buffered and sorted at once.  Otherwise all of the outputs are
+/// buffered.  Errors in each buffered run come before its outputs.
+#[derive(Clone, Debug)]
+pub struct Sorted<'e> {
+    iter: ExpressionIterator<'e>,
+    order: Order,
+    digits: Vec<Digit>,
+    /// The rank of the current value of each digit, or `None` once every run
+    /// has been produced.
+    ranks: Option<Vec<u128>>,
+    /// The number of outputs in each run.
+    run: Option<u128>,
+    buffer: std::vec::IntoIter<Result<Cow<'e, str>, ExpansionError>>,
+}
+
+impl<'e> Sorted<'e> {
+    pub(crate) fn new(iter: ExpressionIterator<'e>, order: Order) -> Self {
+        let parts = iter.expression.parts();
+        let mut digits = Vec::new();
+        let mut run = iter.shape.count();
+        let mut ends_with_digit = false;
+        let mut after_text = false;
+        for (i, part) in parts.iter().enumerate() {
+            // In natural order, a run of digits that continues from the
+            // previous part is ordered by both parts at once.
+            if order == Order::Natural && ends_with_digit && may_start_with_digit(part) {
+                break;
+            }
+            let Some(place) = iter.shape.place(i) else {
+                break;
+            };
+            let last = i + 1 == parts.len();
+            let digits_closed = parts
+                .get(i + 1)
+                .is_some_and(|next| !may_start_with_digit(next));
+            let Some((ranks, ends, empty)) =
+                Ranks::of_part(part, order, last, digits_closed, after_text)
+            else {
+                break;
+            };
+            let Some(count) = part.count() else {
+                break;
+            };
+            digits.push(Digit {
+                ranks,
+                count,
+                place,
+            });
+            run = Some(place);
+            // An empty part leaves whatever came before it.
+            if !empty {
+                ends_with_digit = ends;
+                after_text = true;
+            }
+        }
+        Sorted {
+            ranks: (iter.shape.count() != Some(0)).then(|| vec![0; digits.len()]),
+            iter,
+            order,
+            digits,
+            run,
+            buffer: Vec::new().into_iter(),
+        }
+    }
+
+    /// Buffer and sort the next run of outputs.
+    fn fill(&mut self) -> bool {
+        let Some(ranks) = &mut self.ranks else {
+            return false;
+        };
+        let start = self
+            .digits
+            .iter()
+            .zip(ranks.iter())
+            .map(|(digit, &rank)| digit.ranks.index(rank) * digit.place)
+            .sum();
+        self.iter.skip_to(start);
+        let run = self
+            .run
+            .and_then(|run| usize::try_from(run).ok())
+            .unwrap_or(usize::MAX);
+        let (mut outputs, errors): (Vec<_>, Vec<_>) =
+            self.iter.by_ref().take(run).partition(Result::is_ok);
+        let order = self.order;
+        outputs.sort_by(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => order.compare(a, b),
+            _ => Ordering::Equal,
+        });
+        self.buffer = errors
+            .into_iter()
+            .chain(outputs)
+            .collect::<Vec<_>>()
+            .into_iter();
+
+        // Advance to the next run, with the last digit changing fastest.
+        let mut i = ranks.len();
+        loop {
+            if i == 0 {
+                self.ranks = None;
+                break;
+            }
+            i -= 1;
+            ranks[i] += 1;
+            if ranks[i] < self.digits[i].count {
+                break;
+            }
+            ranks[i] = 0;
+        }
+        true
+    }
+}
+
+impl<'e> Iterator for Sorted<'e> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(output) = self.buffer.next() {
+                return Some(output);
+            }
+            if !self.fill() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_natural_order() {
+        let mut strings = vec!["a10", "a2", "a1", "a01", "b", "a", "a1b", "a1a", "10", "9"];
+        strings.sort_by(|a, b| Order::Natural.compare(a, b));
+        assert_eq!(
+            strings,
+            ["9", "10", "a", "a01", "a1", "a1a", "a1b", "a2", "a10", "b"]
+        );
+    }
+
+    #[test]
+    fn test_sorted_runs() {
+        let test_cases = [
+            ("{=1..99}-{b,a}", Order::Lexicographic, 3, Some(1)),
+            ("{1..99}-{b,a}", Order::Lexicographic, 0, Some(198)),
+            ("{1..99}-{b,a}", Order::Natural, 3, Some(1)),
+            ("{1..99}{b,a}", Order::Natural, 2, Some(1)),
+            ("{1..99}{1,2}", Order::Natural, 0, Some(198)),
+            ("host{z..a}.{x,y,xy}", Order::Lexicographic, 4, Some(1)),
+            ("host{z..a}.{x,xy}-", Order::Lexicographic, 3, Some(2)),
+            ("{a,ab}{c,d}", Order::Lexicographic, 0, Some(4)),
+            ("{a,b}{c,d}", Order::Lexicographic, 2, Some(1)),
+        ];
+        for (input, order, digits, run) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            let sorted = expression.sorted(order);
+            assert_eq!((sorted.digits.len(), sorted.run), (digits, run), "{input}");
+        }
+    }
+}
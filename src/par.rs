@@ -0,0 +1,138 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use rayon::iter::plumbing::{bridge, Consumer, Producer, ProducerCallback, UnindexedConsumer};
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+
+use crate::shape::Shape;
+use crate::{ExpansionError, Expression};
+
+/// A parallel iterator over the outputs of an [`Expression`], created by
+/// [`Expression::par_iter`].
+///
+/// The work is split into ranges of output indices, and each output is
+/// decoded directly from its index, so the threads never need to share any
+/// state.  Collecting keeps the outputs in the usual order.
+#[derive(Clone, Debug)]
+pub struct ParIter<'e, 'a> {
+    expression: &'e Expression<'a>,
+    shape: Shape,
+    len: usize,
+}
+
+impl<'e, 'a> ParIter<'e, 'a> {
+    pub(crate) fn new(expression: &'e Expression<'a>) -> Self {
+        let shape = Shape::of_expression(expression);
+        let len = shape
+            .count()
+            .and_then(|count| usize::try_from(count).ok())
+            .expect("too many outputs to iterate in parallel");
+        ParIter {
+            expression,
+            shape,
+            len,
+        }
+    }
+}
+
+impl<'e> ParallelIterator for ParIter<'e, '_> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    fn drive_unindexed<C>(self, consumer: C) -> C::Result
+    where
+        C: UnindexedConsumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+
+    fn opt_len(&self) -> Option<usize> {
+        Some(self.len)
+    }
+}
+
+impl IndexedParallelIterator for ParIter<'_, '_> {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn drive<C>(self, consumer: C) -> C::Result
+    where
+        C: Consumer<Self::Item>,
+    {
+        bridge(self, consumer)
+    }
+
+    fn with_producer<CB>(self, callback: CB) -> CB::Output
+    where
+        CB: ProducerCallback<Self::Item>,
+    {
+        callback.callback(Outputs {
+            expression: self.expression,
+            shape: &self.shape,
+            range: 0..self.len,
+        })
+    }
+}
+
+/// Decodes a range of outputs, which is the unit of work that [`ParIter`]
+/// splits up between threads.
+struct Outputs<'s, 'e, 'a> {
+    expression: &'e Expression<'a>,
+    shape: &'s Shape,
+    range: Range<usize>,
+}
+
+impl<'e> Outputs<'_, 'e, '_> {
+    fn get(&self, n: usize) -> Result<Cow<'e, str>, ExpansionError> {
+        self.shape
+            .get(self.expression, n as u128, false, &|plain| {
+                Cow::Borrowed(plain.as_ref())
+            })
+            .expect("index out of bounds")
+    }
+}
+
+impl<'e> Producer for Outputs<'_, 'e, '_> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+    type IntoIter = Self;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self
+    }
+
+    fn split_at(self, index: usize) -> (Self, Self) {
+        let middle = self.range.start + index;
+        (
+            Outputs {
+                range: self.range.start..middle,
+                ..self
+            },
+            Outputs {
+                range: middle..self.range.end,
+                ..self
+            },
+        )
+    }
+}
+
+impl<'e> Iterator for Outputs<'_, 'e, '_> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let n = self.range.next()?;
+        Some(self.get(n))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for Outputs<'_, '_, '_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let n = self.range.next_back()?;
+        Some(self.get(n))
+    }
+}
+
+impl ExactSizeIterator for Outputs<'_, '_, '_> {}
@@ -1,151 +1,418 @@
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::sync::Arc;
 
 use nom::{
     branch::alt,
-    bytes::complete::{escaped, is_not, tag},
-    character::complete::{anychar, i64, none_of, one_of, u32, u64},
-    combinator::{all_consuming, complete, opt, success, verify},
-    error::ParseError,
+    bytes::complete::tag,
+    character::complete::{anychar, char, i32, i64, satisfy, u32, u64},
+    combinator::{map, opt, success, verify},
+    error::{ErrorKind, ParseError},
     multi::{many0, many1, separated_list0},
     IResult,
 };
 
-use crate::{Expression, List, Part, Sequence};
-
-// Parse a plain string
-fn plain_str<'a, E: ParseError<&'a str>>(
-    escape_chars: &'static str,
-) -> impl FnMut(&'a str) -> IResult<&'a str, Cow<'a, str>, E> + '_ {
-    move |input: &'a str| -> IResult<&'a str, Cow<'a, str>, E> {
-        let (input, string) = escaped(is_not(escape_chars), '\\', one_of(escape_chars))(input)?;
-        if string.contains("\\") {
-            let mut built = String::with_capacity(string.len());
-            let mut iter = string.chars();
-            while let Some(next) = iter.next() {
-                built.push(if next == '\\' {
-                    // Nom should make sure no trailing backslashes were present.
-                    iter.next().unwrap()
-                } else {
-                    next
-                });
-            }
+use crate::error::ParseErrorKind;
+use crate::{Dialect, Expression, List, Part, Sequence};
 
-            Ok((input, std::borrow::Cow::Owned(built)))
-        } else {
-            Ok((input, std::borrow::Cow::Borrowed(string)))
-        }
-    }
+/// The nom error type used by the parser.
+///
+/// Plain nom errors carry no kind and are only used for backtracking.  Errors
+/// that should be reported to the user are raised as failures with a kind, and
+/// the span of the offending input.
+#[derive(Debug)]
+pub struct Error<'a> {
+    pub input: &'a str,
+    pub len: usize,
+    pub kind: Option<ParseErrorKind>,
+    pub expected: Option<String>,
 }
 
-/// A top-level plain match, which may not be empty and may contain unescaped
-/// commas.
-fn top_plain<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    let (input, s) = verify(plain_str("\\{}"), |s: &str| !s.is_empty())(input)?;
-    Ok((input, Part::Plain(s)))
+impl<'a> Error<'a> {
+    fn failure(
+        input: &'a str,
+        len: usize,
+        kind: ParseErrorKind,
+        expected: Option<String>,
+    ) -> nom::Err<Self> {
+        nom::Err::Failure(Error {
+            input,
+            len,
+            kind: Some(kind),
+            expected,
+        })
+    }
 }
 
-/// A non-top-level plain match, which may not be empty and may not contain
-/// unescaped commas.
-fn list_plain<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    let (input, s) = verify(plain_str("\\{},"), |s: &str| !s.is_empty())(input)?;
-    Ok((input, Part::Plain(s)))
+impl<'a> nom::error::ParseError<&'a str> for Error<'a> {
+    fn from_error_kind(input: &'a str, _kind: ErrorKind) -> Self {
+        Error {
+            input,
+            len: 0,
+            kind: None,
+            expected: None,
+        }
+    }
+
+    fn append(_input: &'a str, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
 }
 
-/// Always succeeds with an empty plain.
-fn empty_plain<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    success(Part::Plain(Cow::Borrowed("")))(input)
+/// A plain error, to backtrack.
+fn backtrack(input: &str) -> nom::Err<Error<'_>> {
+    nom::Err::Error(Error::from_error_kind(input, ErrorKind::Verify))
 }
 
-fn sequence_char<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, char, E> {
-    let (input, c) = none_of(".{},")(input)?;
-    if c == '\\' {
-        anychar(input)
-    } else {
-        Ok((input, c))
+type PResult<'a, T> = IResult<&'a str, T, Error<'a>>;
+
+/// The length of a Bash `${...}` parameter at the start of the input, up to and
+/// including its matching closing delimiter, or the rest of the input if it is
+/// never closed.
+fn parameter_len(input: &str, dialect: &Dialect) -> usize {
+    let mut depth = 0;
+    let mut chars = input.char_indices().skip(1);
+    while let Some((i, c)) = chars.next() {
+        if c == dialect.escape() {
+            chars.next();
+        } else if c == dialect.open() {
+            depth += 1;
+        } else if c == dialect.close() {
+            depth -= 1;
+            if depth == 0 {
+                return i + c.len_utf8();
+            }
+        }
     }
+    input.len()
 }
 
-fn number_sequence_incr<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, u64, E> {
-    let (input, _) = tag("..")(input)?;
-    let (input, incr) = u64(input)?;
-    let incr = if incr < 1 { 1 } else { incr };
-    Ok((input, incr))
+/// Whether a Bash sequence number has a leading zero, which turns on padding.
+fn zero_padded(number: &str) -> bool {
+    let digits = number.strip_prefix(['-', '+']).unwrap_or(number);
+    digits.len() > 1 && digits.starts_with('0')
 }
-fn char_sequence_incr<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, u32, E> {
-    let (input, _) = tag("..")(input)?;
-    let (input, incr) = u32(input)?;
-    let incr = if incr < 1 { 1 } else { incr };
-    Ok((input, incr))
+
+/// The parser, which holds the full source so that parts may record their
+/// spans within it, and the dialect that decides the special characters.
+pub struct Parser<'a, 'd> {
+    source: &'a str,
+    dialect: &'d Dialect,
+
+    /// The dialect to attach to parsed expressions, unless it's the default.
+    shared: Option<Arc<Dialect>>,
+
+    /// Offsets of lists that are known to be unclosed.  Only used by the Bash
+    /// dialect, where an unclosed brace backtracks to be taken literally, to
+    /// avoid exponential backtracking over runs of unclosed braces.
+    unclosed: RefCell<HashSet<usize>>,
 }
 
-fn number_sequence<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    let (input, _) = tag("{")(input)?;
-    let (input, equal) = opt(tag("="))(input)?;
-    let pre_start_len = input.len();
-    let (input, start) = i64(input)?;
-    let post_start_len = input.len();
-    let (input, _) = tag("..")(input)?;
-    let pre_end_len = input.len();
-    let (input, end) = i64(input)?;
-    let post_end_len = input.len();
-    let (input, incr) = opt(number_sequence_incr)(input)?;
-    let (input, _) = tag("}")(input)?;
-    Ok((
-        input,
-        Part::Sequence(Sequence::Int {
-            width: equal.map(|_| {
-                let start_width = pre_start_len - post_start_len;
-                let end_width = pre_end_len - post_end_len;
-                start_width.max(end_width)
-            }),
-            sequence: crate::sequence::Sequence {
-                start,
-                end,
-                incr: incr.unwrap_or(1),
+impl<'a, 'd> Parser<'a, 'd> {
+    pub fn new(source: &'a str, dialect: &'d Dialect) -> Self {
+        Parser {
+            source,
+            dialect,
+            shared: (*dialect != Dialect::bexpand()).then(|| Arc::new(dialect.clone())),
+            unclosed: RefCell::default(),
+        }
+    }
+
+    /// The byte offset of the remaining input within the source.
+    pub fn offset(&self, input: &'a str) -> usize {
+        self.source.len() - input.len()
+    }
+
+    fn expression_of(&self, parts: Vec<Part<'a>>) -> Expression<'a> {
+        Expression {
+            parts,
+            dialect: self.shared.clone(),
+        }
+    }
+
+    // Parse a plain string, stopping at any unescaped special character.  Any
+    // character may be escaped.
+    fn plain_str(&self, input: &'a str, special: &[char]) -> PResult<'a, Cow<'a, str>> {
+        let escape = self.dialect.escape();
+        let mut built: Option<String> = None;
+        let mut end = 0;
+        while let Some(c) = input[end..].chars().next() {
+            if c == escape {
+                let Some(escaped) = input[end + c.len_utf8()..].chars().next() else {
+                    return Err(Error::failure(
+                        &input[end..],
+                        c.len_utf8(),
+                        ParseErrorKind::TrailingEscape,
+                        Some("a character to escape".into()),
+                    ));
+                };
+                built
+                    .get_or_insert_with(|| input[..end].to_owned())
+                    .push(escaped);
+                end += c.len_utf8() + escaped.len_utf8();
+            } else if self.dialect.is_bash()
+                && c == '$'
+                && input[end + 1..].starts_with(self.dialect.open())
+            {
+                // Parameters are taken literally, escapes and all.
+                let len = parameter_len(&input[end..], self.dialect);
+                if let Some(built) = &mut built {
+                    built.push_str(&input[end..end + len]);
+                }
+                end += len;
+            } else if special.contains(&c) {
+                break;
+            } else {
+                if let Some(built) = &mut built {
+                    built.push(c);
+                }
+                end += c.len_utf8();
+            }
+        }
+        let (string, input) = input.split_at(end);
+        match built {
+            Some(built) => Ok((input, Cow::Owned(built))),
+            None => Ok((input, Cow::Borrowed(string))),
+        }
+    }
+
+    /// A top-level plain match, which may not be empty and may contain unescaped
+    /// separators.
+    fn top_plain(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        let special = [self.dialect.open(), self.dialect.close()];
+        let (input, s) = verify(|i| self.plain_str(i, &special), |s: &str| !s.is_empty())(input)?;
+        Ok((input, Part::Plain(s)))
+    }
+
+    /// A non-top-level plain match, which may not be empty and may not contain
+    /// unescaped separators.
+    fn list_plain(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        let special = [
+            self.dialect.open(),
+            self.dialect.close(),
+            self.dialect.separator(),
+        ];
+        let (input, s) = verify(|i| self.plain_str(i, &special), |s: &str| !s.is_empty())(input)?;
+        Ok((input, Part::Plain(s)))
+    }
+
+    /// Always succeeds with an empty plain.
+    fn empty_plain(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        success(Part::Plain(Cow::Borrowed("")))(input)
+    }
+
+    /// A single delimiter taken literally, which only the Bash dialect allows.
+    /// Closing delimiters are only taken at the top level.
+    fn lone_brace(&self, input: &'a str, top: bool) -> PResult<'a, Part<'a>> {
+        if !self.dialect.is_bash() {
+            return Err(backtrack(input));
+        }
+        let (rest, _) =
+            satisfy(|c| c == self.dialect.open() || (top && c == self.dialect.close()))(input)?;
+        Ok((
+            rest,
+            Part::Plain(Cow::Borrowed(&input[..input.len() - rest.len()])),
+        ))
+    }
+
+    fn sequence_char(&self, input: &'a str) -> PResult<'a, char> {
+        if self.dialect.is_bash() {
+            return satisfy(|c| c.is_ascii_alphabetic())(input);
+        }
+        let range = self.dialect.range_operator();
+        let (input, c) =
+            satisfy(|c| !self.dialect.is_special(c) || c == self.dialect.escape())(input)?;
+        if c == self.dialect.escape() {
+            anychar(input)
+        } else if range.starts_with(c) {
+            Err(backtrack(input))
+        } else {
+            Ok((input, c))
+        }
+    }
+
+    /// Parse a stride, after the range operator has already been matched.  A
+    /// stride that isn't a number backtracks, so that the braces are taken as
+    /// a list instead.  With strict strides, a stride that runs up to the
+    /// closing delimiter must be a number, otherwise it's an error, and
+    /// anything else still backtracks, so that an unclosed brace is reported
+    /// as such.
+    fn stride<T>(
+        &self,
+        input: &'a str,
+        mut number: impl FnMut(&'a str) -> PResult<'a, T>,
+    ) -> PResult<'a, T> {
+        let close = self.dialect.close();
+        match number(input) {
+            Ok((rest, incr)) if rest.starts_with(close) => Ok((rest, incr)),
+            _ if !self.dialect.strict_strides() => Err(backtrack(input)),
+            _ => match input.find(|c| self.dialect.is_special(c)) {
+                Some(len) if input[len..].starts_with(close) => Err(Error::failure(
+                    input,
+                    len,
+                    ParseErrorKind::InvalidStride,
+                    Some("a non-negative integer".into()),
+                )),
+                _ => Err(backtrack(input)),
             },
-        }),
-    ))
-}
+        }
+    }
 
-fn char_sequence<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    let (input, _) = tag("{")(input)?;
-    let (input, start) = sequence_char(input)?;
-    let (input, _) = tag("..")(input)?;
-    let (input, end) = sequence_char(input)?;
-    let (input, incr) = opt(char_sequence_incr)(input)?;
-    let (input, _) = tag("}")(input)?;
-    Ok((
-        input,
-        Part::Sequence(Sequence::Char(crate::sequence::Sequence {
-            start,
-            end,
-            incr: incr.unwrap_or(1),
-        })),
-    ))
-}
+    fn number_sequence_incr(&self, input: &'a str) -> PResult<'a, u64> {
+        let (input, _) = tag(self.dialect.range_operator())(input)?;
+        let (input, incr) = if self.dialect.is_bash() {
+            map(i64, i64::unsigned_abs)(input)?
+        } else {
+            self.stride(input, u64)?
+        };
+        let incr = if incr < 1 { 1 } else { incr };
+        Ok((input, incr))
+    }
 
-fn sequence<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    alt((number_sequence, char_sequence))(input)
-}
+    fn char_sequence_incr(&self, input: &'a str) -> PResult<'a, u32> {
+        let (input, _) = tag(self.dialect.range_operator())(input)?;
+        let (input, incr) = if self.dialect.is_bash() {
+            map(i32, i32::unsigned_abs)(input)?
+        } else {
+            self.stride(input, u32)?
+        };
+        let incr = if incr < 1 { 1 } else { incr };
+        Ok((input, incr))
+    }
 
-fn list_expression<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    // A list expression may not be empty and may not contain any non-empty plain parts.
-    let (input, parts) = many1(alt((sequence, list, list_plain)))(input)?;
-    Ok((input, Part::Expression(Expression(parts))))
-}
+    fn number_sequence(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        let (input, _) = char(self.dialect.open())(input)?;
+        let (input, equal) = if self.dialect.is_bash() {
+            (input, None)
+        } else {
+            opt(char('='))(input)?
+        };
+        let (rest, start) = i64(input)?;
+        let start_str = &input[..input.len() - rest.len()];
+        let (input, _) = tag(self.dialect.range_operator())(rest)?;
+        let (rest, end) = i64(input)?;
+        let end_str = &input[..input.len() - rest.len()];
+        let (input, incr) = opt(|i| self.number_sequence_incr(i))(rest)?;
+        let (input, _) = char(self.dialect.close())(input)?;
+        // Bash pads when either end has a leading zero.
+        let padded = if self.dialect.is_bash() {
+            zero_padded(start_str) || zero_padded(end_str)
+        } else {
+            equal.is_some()
+        };
+        Ok((
+            input,
+            Part::Sequence(Sequence::Int {
+                width: padded.then(|| start_str.len().max(end_str.len())),
+                sequence: crate::sequence::Sequence {
+                    start,
+                    end,
+                    incr: incr.unwrap_or(1),
+                },
+            }),
+        ))
+    }
 
-fn list<'a, E: ParseError<&'a str>>(input: &'a str) -> IResult<&'a str, Part<'_>, E> {
-    let (input, _) = tag("{")(input)?;
-    // A list may contain empty plain parts.
-    let (input, items) = separated_list0(tag(","), alt((list_expression, empty_plain)))(input)?;
-    let (input, _) = tag("}")(input)?;
-    Ok((input, Part::List(List(items))))
-}
+    fn char_sequence(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        let open = self.offset(input);
+        let (input, _) = char(self.dialect.open())(input)?;
+        let (input, start) = self.sequence_char(input)?;
+        let (input, _) = tag(self.dialect.range_operator())(input)?;
+        let (input, end) = self.sequence_char(input)?;
+        let (input, incr) = opt(|i| self.char_sequence_incr(i))(input)?;
+        let (input, _) = char(self.dialect.close())(input)?;
+        Ok((
+            input,
+            Part::Sequence(Sequence::Char {
+                sequence: crate::sequence::Sequence {
+                    start,
+                    end,
+                    incr: incr.unwrap_or(1),
+                },
+                span: Some(open..self.offset(input)),
+            }),
+        ))
+    }
+
+    fn sequence(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        alt((|i| self.number_sequence(i), |i| self.char_sequence(i)))(input)
+    }
+
+    fn list_expression(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        // A list expression may not be empty and may not contain any non-empty plain parts.
+        let (input, parts) = many1(alt((
+            |i| self.sequence(i),
+            |i| self.list(i),
+            |i| self.list_plain(i),
+            |i| self.lone_brace(i, false),
+        )))(input)?;
+        Ok((input, Part::Expression(self.expression_of(parts))))
+    }
 
-pub fn expression<'a, E: ParseError<&'a str>>(
-    input: &'a str,
-) -> IResult<&'a str, Expression<'_>, E> {
-    // A top level expression may be empty, and may not contain any non-empty plain parts
-    let (input, parts) = all_consuming(complete(many0(alt((sequence, list, top_plain)))))(input)?;
-    Ok((input, Expression(parts)))
+    fn list(&self, input: &'a str) -> PResult<'a, Part<'a>> {
+        let open = input;
+        let bash = self.dialect.is_bash();
+        if bash && self.unclosed.borrow().contains(&self.offset(open)) {
+            return Err(backtrack(open));
+        }
+        let (input, _) = char(self.dialect.open())(input)?;
+        // A list may contain empty plain parts.
+        let (input, items) = separated_list0(
+            char(self.dialect.separator()),
+            alt((|i| self.list_expression(i), |i| self.empty_plain(i))),
+        )(input)?;
+        // Items only stop at a separator or a closing delimiter, so anything
+        // else is the end of input.
+        let (rest, _) = char(self.dialect.close())(input).map_err(|_: nom::Err<Error>| {
+            if bash {
+                // Bash takes the brace literally instead.
+                self.unclosed.borrow_mut().insert(self.offset(open));
+                backtrack(open)
+            } else {
+                Error::failure(
+                    open,
+                    self.dialect.open().len_utf8(),
+                    ParseErrorKind::UnclosedBrace,
+                    Some(format!("`{}`", self.dialect.close())),
+                )
+            }
+        })?;
+        let close = &input[..input.len() - rest.len()];
+        let input = rest;
+        if bash && items.len() < 2 {
+            // Without a separator, Bash takes the braces literally, but still
+            // expands their contents.
+            let open = &open[..self.dialect.open().len_utf8()];
+            let mut parts = vec![Part::Plain(Cow::Borrowed(open))];
+            for item in items {
+                if let Part::Expression(item) = item {
+                    parts.extend(item.parts);
+                }
+            }
+            parts.push(Part::Plain(Cow::Borrowed(close)));
+            return Ok((input, Part::Expression(self.expression_of(parts))));
+        }
+        Ok((input, Part::List(List(items))))
+    }
+
+    pub fn expression(&self) -> PResult<'a, Expression<'a>> {
+        // A top level expression may be empty, and may not contain any non-empty plain parts
+        let (input, parts) = many0(alt((
+            |i| self.sequence(i),
+            |i| self.list(i),
+            |i| self.top_plain(i),
+            |i| self.lone_brace(i, true),
+        )))(self.source)?;
+        // Top level parts only stop at an unmatched closing delimiter.
+        if !input.is_empty() {
+            return Err(Error::failure(
+                input,
+                self.dialect.close().len_utf8(),
+                ParseErrorKind::UnexpectedCloseBrace,
+                None,
+            ));
+        }
+        Ok((input, self.expression_of(parts)))
+    }
 }
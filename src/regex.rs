@@ -0,0 +1,225 @@
+//! Compiling expressions to regular expressions.
+//!
+//! The patterns only use syntax that is common to PCRE, RE2 and the `regex`
+//! crate: non-capturing groups, alternations, character classes and counted
+//! repetition.
+
+use crate::{sequence, Expression, Part, Sequence};
+
+/// A pattern that never matches, for expressions without any outputs.
+const NEVER: &str = r"[^\s\S]";
+
+fn escape(s: &str, pattern: &mut String) {
+    for c in s.chars() {
+        if r"\.+*?()|[]{}^$".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+}
+
+fn escape_in_class(c: char, pattern: &mut String) {
+    if r"\[]^-&~".contains(c) {
+        pattern.push('\\');
+    }
+    pattern.push(c);
+}
+
+/// Join alternatives into a single pattern, grouped so that it can be
+/// concatenated with others.
+fn alternation(mut alternatives: Vec<String>) -> String {
+    match alternatives.len() {
+        0 => NEVER.to_string(),
+        1 => alternatives.pop().unwrap(),
+        _ => format!("(?:{})", alternatives.join("|")),
+    }
+}
+
+pub(crate) fn expression(expression: &Expression<'_>) -> String {
+    if expression.count() == Some(0) {
+        return NEVER.to_string();
+    }
+    expression.parts().iter().map(part).collect()
+}
+
+fn part(part: &Part<'_>) -> String {
+    match part {
+        Part::Plain(plain) => {
+            let mut pattern = String::new();
+            escape(plain, &mut pattern);
+            pattern
+        }
+        Part::List(list) => alternation(
+            list.items()
+                .iter()
+                .filter(|item| item.count() != Some(0))
+                .map(self::part)
+                .collect(),
+        ),
+        Part::Sequence(Sequence::Int { width, sequence }) => int_sequence(*width, sequence),
+        Part::Sequence(Sequence::Char { sequence, .. }) => char_sequence(sequence),
+        Part::Expression(expression) => self::expression(expression),
+    }
+}
+
+fn int_sequence(width: Option<usize>, sequence: &sequence::Sequence<i64>) -> String {
+    let format = |number: i64| match width {
+        Some(width) => format!("{number:0width$}"),
+        None => number.to_string(),
+    };
+    if sequence.count() == 1 {
+        // A stride wider than the span only reaches the start.
+        return format(sequence.start);
+    }
+    if sequence.incr > 1 {
+        // A stride can't be expressed, so list every number.
+        return alternation(
+            (0..sequence.count())
+                .filter_map(|n| sequence.value(n).ok())
+                .map(format)
+                .collect(),
+        );
+    }
+    let low = i128::from(sequence.start.min(sequence.end));
+    let high = i128::from(sequence.start.max(sequence.end));
+    let width = width.unwrap_or(1);
+    let mut alternatives = Vec::new();
+    if low < 0 {
+        // The width of a negative number includes its sign.
+        let magnitudes = high.min(-1).unsigned_abs()..=low.unsigned_abs();
+        let digits = width.saturating_sub(1).max(1);
+        for alternative in padded_range(magnitudes, digits) {
+            alternatives.push(format!("-{alternative}"));
+        }
+    }
+    if high >= 0 {
+        alternatives.extend(padded_range(low.max(0) as u128..=high as u128, width));
+    }
+    alternation(alternatives)
+}
+
+/// Alternatives that match every number in `range`, zero-padded to `width`.
+fn padded_range(range: std::ops::RangeInclusive<u128>, width: usize) -> Vec<String> {
+    let (mut low, high) = range.into_inner();
+    let mut alternatives = Vec::new();
+    // Numbers of each length are a range of digit strings of that length.
+    loop {
+        let len = low.to_string().len().max(width);
+        let end = high.min(10u128.pow(len as u32) - 1);
+        alternatives.extend(digit_range(
+            format!("{low:0len$}").as_bytes(),
+            format!("{end:0len$}").as_bytes(),
+        ));
+        if end == high {
+            return alternatives;
+        }
+        low = end + 1;
+    }
+}
+
+fn digit_class(first: u8, last: u8) -> String {
+    match last - first {
+        0 => char::from(first).to_string(),
+        1 => format!("[{}{}]", char::from(first), char::from(last)),
+        _ => format!("[{}-{}]", char::from(first), char::from(last)),
+    }
+}
+
+fn any_digits(len: usize) -> String {
+    match len {
+        0 => String::new(),
+        1 => "[0-9]".to_string(),
+        len => format!("[0-9]{{{len}}}"),
+    }
+}
+
+/// Alternatives that match every string of digits from `low` to `high`,
+/// which have the same length.
+fn digit_range(low: &[u8], high: &[u8]) -> Vec<String> {
+    let (Some((&first, low_rest)), Some((&last, high_rest))) =
+        (low.split_first(), high.split_first())
+    else {
+        return vec![String::new()];
+    };
+    let prefixed = |digit: u8, alternatives: Vec<String>| {
+        alternatives
+            .into_iter()
+            .map(move |alternative| format!("{}{alternative}", char::from(digit)))
+    };
+    if first == last {
+        return prefixed(first, digit_range(low_rest, high_rest)).collect();
+    }
+    let len = low_rest.len();
+    let low_full = low_rest.iter().all(|&digit| digit == b'0');
+    let high_full = high_rest.iter().all(|&digit| digit == b'9');
+    let mut alternatives = Vec::new();
+    if !low_full {
+        alternatives.extend(prefixed(first, digit_range(low_rest, &vec![b'9'; len])));
+    }
+    // The first digits in between take any digits after them.
+    let middle_first = if low_full { first } else { first + 1 };
+    let middle_last = if high_full { last } else { last - 1 };
+    if middle_first <= middle_last {
+        alternatives.push(digit_class(middle_first, middle_last) + &any_digits(len));
+    }
+    if !high_full {
+        alternatives.extend(prefixed(last, digit_range(&vec![b'0'; len], high_rest)));
+    }
+    alternatives
+}
+
+fn char_sequence(sequence: &sequence::Sequence<char>) -> String {
+    let mut pattern = String::new();
+    if sequence.count() == 1 {
+        escape(sequence.start.encode_utf8(&mut [0; 4]), &mut pattern);
+        return pattern;
+    }
+    pattern.push('[');
+    if sequence.incr <= 1 {
+        escape_in_class(sequence.start.min(sequence.end), &mut pattern);
+        pattern.push('-');
+        escape_in_class(sequence.start.max(sequence.end), &mut pattern);
+    } else {
+        // Values in the surrogate range are errors rather than outputs.
+        let values: Vec<_> = (0..sequence.count())
+            .filter_map(|n| sequence.value(n).ok())
+            .collect();
+        if values.is_empty() {
+            return NEVER.to_string();
+        }
+        for c in values {
+            escape_in_class(c, &mut pattern);
+        }
+    }
+    pattern.push(']');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digit_range() {
+        let test_cases = [
+            ("1", "250", "(?:[1-9]|[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|250)"),
+            (
+                "=1",
+                "250",
+                "(?:00[1-9]|0[1-9][0-9]|1[0-9]{2}|2[0-4][0-9]|250)",
+            ),
+            ("0", "9", "[0-9]"),
+            ("-10", "10", "(?:-[1-9]|-10|[0-9]|10)"),
+            ("=-5", "5", "(?:-[1-5]|0[0-5])"),
+            ("7", "7", "7"),
+            ("1", "100..33", "(?:1|34|67|100)"),
+            ("2", "0..3", "2"),
+            ("0", "-2..3", "0"),
+        ];
+        for (start, end, expected) in test_cases {
+            let source = format!("{{{start}..{end}}}");
+            let expression = Expression::try_from(source.as_str()).unwrap();
+            assert_eq!(self::expression(&expression), expected, "{source}");
+        }
+    }
+}
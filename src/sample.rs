@@ -0,0 +1,74 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::shape::Shape;
+use crate::{ExpansionError, Expression};
+
+/// Iterates over randomly sampled outputs of an [`Expression`], created by
+/// [`Expression::sample`] or [`Expression::sample_distinct`].
+///
+/// Each output is decoded directly from a random index, so the expression's
+/// outputs are never all produced.
+#[derive(Debug)]
+pub struct Sample<'e, 'a, R> {
+    expression: &'e Expression<'a>,
+    shape: Shape,
+    rng: R,
+    /// The number of outputs left to sample.
+    remaining: usize,
+    /// The indices sampled so far, when sampling without replacement.
+    seen: Option<HashSet<u128>>,
+}
+
+impl<'e, 'a, R> Sample<'e, 'a, R>
+where
+    R: rand::Rng,
+{
+    pub(crate) fn new(expression: &'e Expression<'a>, rng: R, n: usize, distinct: bool) -> Self {
+        let shape = Shape::of_expression(expression);
+        let remaining = match shape.count() {
+            Some(count) if distinct => n.min(usize::try_from(count).unwrap_or(usize::MAX)),
+            Some(0) => 0,
+            _ => n,
+        };
+        Sample {
+            expression,
+            shape,
+            rng,
+            remaining,
+            seen: distinct.then(HashSet::new),
+        }
+    }
+}
+
+impl<'e, R> Iterator for Sample<'e, '_, R>
+where
+    R: rand::Rng,
+{
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let plain = |plain: &'e Cow<'_, str>| Cow::Borrowed(plain.as_ref());
+        loop {
+            let (index, output) = self.shape.random(self.expression, &mut self.rng, &plain);
+            // An index that doesn't fit can't practically repeat.
+            let repeated = match (&mut self.seen, index) {
+                (Some(seen), Some(index)) => !seen.insert(index),
+                _ => false,
+            };
+            if !repeated {
+                return Some(output);
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<R> ExactSizeIterator for Sample<'_, '_, R> where R: rand::Rng {}
@@ -1,7 +1,6 @@
-/// Implement a Sequence IntoIterator.
-/// Unfortunately, we can't just use a RangeInclusive here, because `char`
-/// doesn't work, so we have to reinvent some wheels.
-use std::cmp::Ordering;
+//! Implement a Sequence IntoIterator.
+//! Unfortunately, we can't just use a RangeInclusive here, because `char`
+//! doesn't work, so we have to reinvent some wheels.
 use std::fmt::Debug;
 
 /// A checked add and sub trait that enforces uniform checked unsigned addition
@@ -9,17 +8,20 @@ use std::fmt::Debug;
 /// whether they need `checked_add` and `checked_sub` or `checked_add_unsigned`
 /// and `checked_sub_unsigned`.
 pub trait CheckedAddSub: Copy + Clone + Debug {
-    type Arithmetic: Copy + Clone + Debug;
+    type Arithmetic: Copy + Clone + Debug + TryInto<u128> + TryFrom<u128>;
 
     fn checked_add(self, rhs: Self::Arithmetic) -> Option<Self>;
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self>;
+
+    /// The absolute difference, which always fits in the unsigned type.
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic;
 }
 
 /// A type contained in a sequence.  In practice, this will only ever be `i64`
 /// or `char`, but it doesn't hurt to allow other types to work with it, too.
 pub trait SequenceItem: Copy + Clone + Debug {
     /// Unsigned incrementation type.
-    type Arithmetic: Copy + Clone + Debug;
+    type Arithmetic: Copy + Clone + Debug + TryInto<u128> + TryFrom<u128>;
 
     /// Arithmetic proxy type, because some types (like char) don't impl `Add`
     /// and `Sub` directly.
@@ -39,6 +41,9 @@ impl CheckedAddSub for isize {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub_unsigned(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for i64 {
     type Arithmetic = u64;
@@ -48,6 +53,9 @@ impl CheckedAddSub for i64 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub_unsigned(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for i32 {
     type Arithmetic = u32;
@@ -57,6 +65,9 @@ impl CheckedAddSub for i32 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub_unsigned(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for i16 {
     type Arithmetic = u16;
@@ -66,6 +77,9 @@ impl CheckedAddSub for i16 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub_unsigned(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for i8 {
     type Arithmetic = u8;
@@ -75,6 +89,9 @@ impl CheckedAddSub for i8 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub_unsigned(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for usize {
     type Arithmetic = usize;
@@ -84,6 +101,9 @@ impl CheckedAddSub for usize {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for u64 {
     type Arithmetic = u64;
@@ -93,6 +113,9 @@ impl CheckedAddSub for u64 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for u32 {
     type Arithmetic = u32;
@@ -102,6 +125,9 @@ impl CheckedAddSub for u32 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for u16 {
     type Arithmetic = u16;
@@ -111,6 +137,9 @@ impl CheckedAddSub for u16 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 impl CheckedAddSub for u8 {
     type Arithmetic = u8;
@@ -120,6 +149,9 @@ impl CheckedAddSub for u8 {
     fn checked_sub(self, rhs: Self::Arithmetic) -> Option<Self> {
         self.checked_sub(rhs)
     }
+    fn abs_diff(self, rhs: Self) -> Self::Arithmetic {
+        self.abs_diff(rhs)
+    }
 }
 
 impl SequenceItem for char {
@@ -178,55 +210,150 @@ where
     pub incr: T::Arithmetic,
 }
 
+/// Convert an unsigned arithmetic value to u128, which is always possible for
+/// the primitive types.
+fn to_u128<A: TryInto<u128>>(value: A) -> u128 {
+    value
+        .try_into()
+        .unwrap_or_else(|_| panic!("arithmetic type does not fit in u128"))
+}
+
+impl<T> Sequence<T>
+where
+    T: SequenceItem,
+{
+    /// The number of items in the sequence, including any that fail to
+    /// convert.  A zero incr is counted as `1`.
+    pub fn count(&self) -> u128 {
+        let start: T::Proxy = self.start.into();
+        to_u128(start.abs_diff(self.end.into())) / self.stride() + 1
+    }
+
+    /// The item at index `n`, or `None` if the sequence is shorter than that.
+    /// An item that can't be converted back from the proxy type is returned as
+    /// the raw proxy value.
+    pub fn get(&self, n: u128) -> Option<Result<T, T::Proxy>> {
+        (n < self.count()).then(|| self.value(n))
+    }
+
+    /// The index of `value` in the sequence, or `None` if the sequence never
+    /// reaches it.
+    pub fn position(&self, value: T) -> Option<u128> {
+        let (start, end, value): (T::Proxy, T::Proxy, T::Proxy) =
+            (self.start.into(), self.end.into(), value.into());
+        if value < start.min(end) || value > start.max(end) {
+            return None;
+        }
+        let offset = to_u128(start.abs_diff(value));
+        (offset % self.stride() == 0).then(|| offset / self.stride())
+    }
+
+    fn stride(&self) -> u128 {
+        to_u128(self.incr).max(1)
+    }
+
+    /// The item at index `n`, which must be in bounds.
+    pub(crate) fn value(&self, n: u128) -> Result<T, T::Proxy> {
+        // In bounds, the offset is at most the distance between start and end,
+        // so it always fits.
+        let offset = T::Arithmetic::try_from(n * self.stride())
+            .unwrap_or_else(|_| panic!("sequence offset out of range"));
+        let start: T::Proxy = self.start.into();
+        let value = if start <= self.end.into() {
+            start.checked_add(offset)
+        } else {
+            start.checked_sub(offset)
+        }
+        .expect("sequence offset out of range");
+        value.try_into().map_err(|_| value)
+    }
+}
+
+/// Iterates the items of a [`Sequence`] in order.
 #[derive(Copy, Clone, Debug)]
 pub struct SequenceIterator<T>
 where
     T: SequenceItem,
 {
-    next: Option<T::Proxy>,
-    end: T::Proxy,
-    incr: T::Arithmetic,
+    sequence: Sequence<T>,
+    /// The index of the next item.
+    front: u128,
+    /// One past the index of the last item.
+    back: u128,
 }
 
 impl<T> IntoIterator for Sequence<T>
 where
     T: SequenceItem,
 {
-    type Item = Result<T, <T::Proxy as TryInto<T>>::Error>;
+    type Item = Result<T, T::Proxy>;
 
     type IntoIter = SequenceIterator<T>;
 
     fn into_iter(self) -> Self::IntoIter {
         SequenceIterator {
-            next: Some(self.start.into()),
-            end: self.end.into(),
-            incr: self.incr,
+            front: 0,
+            back: self.count(),
+            sequence: self,
         }
     }
 }
 
+impl<T> SequenceIterator<T>
+where
+    T: SequenceItem,
+{
+    /// The number of items left.
+    pub fn remaining(&self) -> u128 {
+        self.back - self.front
+    }
+}
+
 impl<T> Iterator for SequenceIterator<T>
 where
     T: SequenceItem,
 {
-    type Item = Result<T, <T::Proxy as TryInto<T>>::Error>;
+    /// An item that can't be converted back from the proxy type (like a
+    /// surrogate codepoint for `char`) is returned as the raw proxy value.
+    type Item = Result<T, T::Proxy>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let next = self.next;
-        if let Some(next) = next {
-            self.next = match next.cmp(&self.end) {
-                Ordering::Less => {
-                    // Going upwards, add incr.
-                    next.checked_add(self.incr).filter(|next| next <= &self.end)
-                }
-                Ordering::Equal => None,
-                Ordering::Greater => {
-                    // Going downwards, subtract incr.
-                    next.checked_sub(self.incr).filter(|next| next >= &self.end)
-                }
-            };
+        if self.front == self.back {
+            return None;
+        }
+        let value = self.sequence.value(self.front);
+        self.front += 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match usize::try_from(self.remaining()) {
+            Ok(remaining) => (remaining, Some(remaining)),
+            Err(_) => (usize::MAX, None),
         }
-        next.map(TryInto::try_into)
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.front = self.front.saturating_add(n as u128).min(self.back);
+        self.next()
+    }
+}
+
+impl<T> DoubleEndedIterator for SequenceIterator<T>
+where
+    T: SequenceItem,
+{
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.sequence.value(self.back))
+    }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        self.back = self.back.saturating_sub(n as u128).max(self.front);
+        self.next_back()
     }
 }
 
@@ -345,4 +472,139 @@ mod tests {
         let values: Result<Vec<_>, _> = sequence.into_iter().collect();
         assert_eq!(&values.unwrap(), &['f', 'd', 'b']);
     }
+
+    #[test]
+    fn test_count() {
+        let test_cases = [
+            (
+                Sequence {
+                    start: 1i64,
+                    end: 10,
+                    incr: 1,
+                }
+                .count(),
+                10,
+            ),
+            (
+                Sequence {
+                    start: 10i64,
+                    end: 1,
+                    incr: 4,
+                }
+                .count(),
+                3,
+            ),
+            (
+                Sequence {
+                    start: 1i64,
+                    end: 1,
+                    incr: 0,
+                }
+                .count(),
+                1,
+            ),
+            (
+                Sequence {
+                    start: i64::MIN,
+                    end: i64::MAX,
+                    incr: 1,
+                }
+                .count(),
+                1 << 64,
+            ),
+            (
+                Sequence {
+                    start: '\u{D7FF}',
+                    end: '\u{E000}',
+                    incr: 1,
+                }
+                .count(),
+                0x802,
+            ),
+        ];
+        for (count, expected) in test_cases {
+            assert_eq!(count, expected);
+        }
+        let mut iter = Sequence {
+            start: 1i64,
+            end: 10,
+            incr: 3,
+        }
+        .into_iter();
+        assert_eq!(iter.size_hint(), (4, Some(4)));
+        iter.next();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        iter.by_ref().for_each(drop);
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+    }
+
+    #[test]
+    fn test_random_access() {
+        let sequence = Sequence {
+            start: 'z',
+            end: 'a',
+            incr: 5,
+        };
+        assert_eq!(sequence.get(0), Some(Ok('z')));
+        assert_eq!(sequence.get(5), Some(Ok('a')));
+        assert_eq!(sequence.get(6), None);
+        let mut iter = sequence.into_iter();
+        assert_eq!(iter.nth(2), Some(Ok('p')));
+        assert_eq!(iter.next(), Some(Ok('k')));
+        assert_eq!(iter.nth(5), None);
+        assert_eq!(iter.next(), None);
+
+        let sequence = Sequence {
+            start: i64::MAX,
+            end: i64::MIN,
+            incr: u64::MAX,
+        };
+        let values: Vec<_> = sequence.into_iter().collect();
+        assert_eq!(values, [Ok(i64::MAX), Ok(i64::MIN)]);
+        let surrogates = Sequence {
+            start: '\u{D7FF}',
+            end: '\u{E000}',
+            incr: 1,
+        };
+        assert_eq!(surrogates.get(1), Some(Err(0xD800)));
+    }
+
+    #[test]
+    fn test_position() {
+        let sequence = Sequence {
+            start: 10i64,
+            end: -10,
+            incr: 4,
+        };
+        assert_eq!(sequence.position(10), Some(0));
+        assert_eq!(sequence.position(-6), Some(4));
+        assert_eq!(sequence.position(-10), Some(5));
+        assert_eq!(sequence.position(-8), None);
+        assert_eq!(sequence.position(12), None);
+        assert_eq!(sequence.position(7), None);
+        let sequence = Sequence {
+            start: 'a',
+            end: 'z',
+            incr: 1,
+        };
+        assert_eq!(sequence.position('q'), Some(16));
+        assert_eq!(sequence.position('A'), None);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let sequence = Sequence {
+            start: 1i64,
+            end: 10,
+            incr: 4,
+        };
+        let values: Vec<_> = sequence.into_iter().rev().collect();
+        assert_eq!(values, [Ok(9), Ok(5), Ok(1)]);
+        let mut iter = sequence.into_iter();
+        assert_eq!(iter.next(), Some(Ok(1)));
+        assert_eq!(iter.nth_back(0), Some(Ok(9)));
+        assert_eq!(iter.next_back(), Some(Ok(5)));
+        assert_eq!(iter.next_back(), None);
+        assert_eq!(iter.next(), None);
+    }
 }
@@ -0,0 +1,621 @@
+//! Random access to the outputs of an expression by their index.
+//!
+//! Outputs are ordered as a mixed-radix number, where each part of an
+//! expression is a digit whose radix is the number of values that part
+//! produces, and the last part is the least significant.  A list's values are
+//! the values of each of its items in turn.  Decoding an index therefore only
+//! has to descend through the tree once.
+
+use std::borrow::Cow;
+use std::fmt::{self, Write};
+
+use crate::{Dialect, ExpansionError, Expression, Part, Sequence};
+
+/// Displays a sequence in the dialect of the expression it came from, which
+/// is what the user wrote, rather than the default dialect.
+struct WithDialect<'s>(&'s Sequence, &'s Dialect);
+
+impl fmt::Display for WithDialect<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_with(f, self.1)
+    }
+}
+
+/// The output counts of every node of an expression, mirroring its tree, so
+/// that outputs can be decoded without recounting.
+#[derive(Clone, Debug)]
+pub(crate) struct Shape {
+    /// The number of values the node produces, or `None` if that overflows a
+    /// `u128`.
+    count: Option<u128>,
+    /// The shapes of the parts of an expression or the items of a list.
+    children: Vec<Shape>,
+    /// For an expression, the place value of each part, which is the number
+    /// of values of all the parts after it, or `None` if that overflows.
+    places: Vec<Option<u128>>,
+    /// For a list, the index of the first value of each item, or `None` if
+    /// that overflows.
+    starts: Vec<Option<u128>>,
+}
+
+/// Whether `n` is a valid index into something with `count` values, where a
+/// `None` count is larger than any index.
+fn in_bounds(n: u128, count: Option<u128>) -> bool {
+    count.is_none_or(|count| n < count)
+}
+
+/// Append a piece of an output, borrowing it if it's the only non-empty one.
+/// An output that is already owned stays owned, so that its buffer is reused.
+fn push<'o>(output: &mut Cow<'o, str>, piece: Cow<'o, str>) {
+    match output {
+        Cow::Borrowed("") => *output = piece,
+        output => output.to_mut().push_str(&piece),
+    }
+}
+
+impl Shape {
+    fn leaf(count: u128) -> Self {
+        Shape {
+            count: Some(count),
+            children: Vec::new(),
+            places: Vec::new(),
+            starts: Vec::new(),
+        }
+    }
+
+    pub(crate) fn of_expression(expression: &Expression<'_>) -> Self {
+        let children: Vec<_> = expression.parts().iter().map(Shape::of_part).collect();
+        let mut places = vec![Some(1u128); children.len()];
+        for i in (1..children.len()).rev() {
+            places[i - 1] = places[i]
+                .zip(children[i].count)
+                .and_then(|(place, count)| place.checked_mul(count));
+        }
+        let count = if children.is_empty() || children.iter().any(|child| child.count == Some(0)) {
+            // A part without any values makes the count zero, even if the
+            // other parts overflow.
+            Some(0)
+        } else {
+            places[0]
+                .zip(children[0].count)
+                .and_then(|(place, count)| place.checked_mul(count))
+        };
+        Shape {
+            count,
+            children,
+            places,
+            starts: Vec::new(),
+        }
+    }
+
+    fn of_part(part: &Part<'_>) -> Self {
+        match part {
+            Part::Plain(_) => Shape::leaf(1),
+            Part::Sequence(sequence) => Shape::leaf(sequence.count()),
+            Part::List(list) => {
+                let children: Vec<_> = list.items().iter().map(Shape::of_part).collect();
+                let mut starts = Vec::with_capacity(children.len());
+                let mut count = Some(0u128);
+                for child in &children {
+                    starts.push(count);
+                    count = count
+                        .zip(child.count)
+                        .and_then(|(count, child)| count.checked_add(child));
+                }
+                Shape {
+                    count,
+                    children,
+                    places: Vec::new(),
+                    starts,
+                }
+            }
+            Part::Expression(expression) => Shape::of_expression(expression),
+        }
+    }
+
+    /// The number of outputs, or `None` if that overflows a `u128`.
+    pub(crate) fn count(&self) -> Option<u128> {
+        self.count
+    }
+
+    /// The place value of part `i` of an expression, or `None` if that
+    /// overflows a `u128`.
+    pub(crate) fn place(&self, i: usize) -> Option<u128> {
+        self.places[i]
+    }
+
+    /// Find the item of the list this is the shape of that value `n` falls
+    /// into, and the index of the value within that item, both counting from
+    /// the back if `rev` is set.
+    fn find_item(&self, n: u128, rev: bool) -> (usize, u128) {
+        match (rev, self.count) {
+            (false, _) => {
+                // Items without any values share their start with the next
+                // item, so this takes the last item that starts at or before
+                // `n`.
+                let i = self
+                    .starts
+                    .partition_point(|start| start.is_some_and(|start| start <= n))
+                    - 1;
+                (i, n - self.starts[i].expect("index out of bounds"))
+            }
+            (true, Some(count)) => {
+                let (i, n) = self.find_item(count - 1 - n, false);
+                (
+                    i,
+                    self.children[i].count.expect("index out of bounds") - 1 - n,
+                )
+            }
+            (true, None) => {
+                // Without a count, the starts can't be counted from the back.
+                let mut n = n;
+                for (i, shape) in self.children.iter().enumerate().rev() {
+                    match shape.count {
+                        Some(count) if n >= count => n -= count,
+                        _ => return (i, n),
+                    }
+                }
+                panic!("index out of bounds");
+            }
+        }
+    }
+
+    /// Decode output `n` of the expression this is the shape of, counting from
+    /// the back if `rev` is set.  Plain parts are turned into pieces of the
+    /// output by `plain`, which decides whether they are borrowed or cloned.
+    ///
+    /// The index of an error is the index from the front, which saturates if
+    /// the count overflows.
+    pub(crate) fn get<'s, 'a, 'o, F>(
+        &self,
+        expression: &'s Expression<'a>,
+        n: u128,
+        rev: bool,
+        plain: &F,
+    ) -> Option<Result<Cow<'o, str>, ExpansionError>>
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+    {
+        if !in_bounds(n, self.count) {
+            return None;
+        }
+        let mut output = Cow::Borrowed("");
+        Some(
+            self.decode(expression, n, rev, plain, expression.dialect(), &mut output)
+                .map(|()| output)
+                .map_err(|error| ExpansionError {
+                    index: match (rev, self.count) {
+                        (false, _) => n,
+                        (true, Some(count)) => count - 1 - n,
+                        (true, None) => u128::MAX,
+                    },
+                    ..error
+                }),
+        )
+    }
+
+    fn decode<'s, 'a, 'o, F>(
+        &self,
+        expression: &'s Expression<'a>,
+        n: u128,
+        rev: bool,
+        plain: &F,
+        dialect: &Dialect,
+        output: &mut Cow<'o, str>,
+    ) -> Result<(), ExpansionError>
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+    {
+        let parts = expression.parts().iter().zip(&self.children);
+        for ((part, shape), place) in parts.zip(&self.places) {
+            // When counting from the back, each digit is decoded from the back
+            // too, which reverses the whole order.
+            let digit = place.map_or(0, |place| n / place);
+            let digit = shape.count.map_or(digit, |count| digit % count);
+            shape.decode_part(part, digit, rev, plain, dialect, output)?;
+        }
+        Ok(())
+    }
+
+    fn decode_part<'s, 'a, 'o, F>(
+        &self,
+        part: &'s Part<'a>,
+        n: u128,
+        rev: bool,
+        plain: &F,
+        dialect: &Dialect,
+        output: &mut Cow<'o, str>,
+    ) -> Result<(), ExpansionError>
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+    {
+        match part {
+            Part::Plain(part) => push(output, plain(part)),
+            Part::Sequence(sequence) => {
+                let n = if rev { sequence.count() - 1 - n } else { n };
+                match sequence {
+                    Sequence::Int { width, sequence } => {
+                        let number = sequence.value(n).unwrap();
+                        let output = output.to_mut();
+                        match *width {
+                            Some(width) => write!(output, "{number:0width$}"),
+                            None => write!(output, "{number}"),
+                        }
+                        .expect("writing to a String can't fail");
+                    }
+                    Sequence::Char { sequence, span } => match sequence.value(n) {
+                        Ok(c) => output.to_mut().push(c),
+                        Err(codepoint) => {
+                            return Err(ExpansionError {
+                                sequence: WithDialect(
+                                    &Sequence::Char {
+                                        sequence: *sequence,
+                                        span: None,
+                                    },
+                                    dialect,
+                                )
+                                .to_string(),
+                                span: span.clone(),
+                                codepoint,
+                                index: 0,
+                            })
+                        }
+                    },
+                }
+            }
+            Part::List(list) => {
+                let (i, n) = self.find_item(n, rev);
+                self.children[i].decode_part(&list.items()[i], n, rev, plain, dialect, output)?;
+            }
+            Part::Expression(expression) => {
+                self.decode(expression, n, rev, plain, dialect, output)?
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Shape {
+    /// Call `f` with every output of the expression this is the shape of, in
+    /// order, stopping at the first error.
+    ///
+    /// All the outputs are built in the same buffer.  Like an odometer, moving
+    /// from one output to the next only rewrites the parts from the one that
+    /// advanced onwards, which is usually just the last.
+    pub(crate) fn for_each<F>(
+        &self,
+        expression: &Expression<'_>,
+        mut f: F,
+    ) -> Result<(), ExpansionError>
+    where
+        F: FnMut(&str),
+    {
+        if self.count == Some(0) {
+            return Ok(());
+        }
+        let parts = expression.parts();
+        // The index of the current value of each part, and the length of the
+        // output before it.
+        let mut digits = vec![0u128; parts.len()];
+        let mut marks = vec![0usize; parts.len()];
+        let mut output: Cow<str> = Cow::Owned(String::new());
+        let mut index = 0u128;
+        let mut changed = 0;
+        loop {
+            output.to_mut().truncate(marks[changed]);
+            for i in changed..parts.len() {
+                marks[i] = output.len();
+                self.children[i]
+                    .decode_part(
+                        &parts[i],
+                        digits[i],
+                        false,
+                        &|plain| Cow::Borrowed(plain),
+                        expression.dialect(),
+                        &mut output,
+                    )
+                    .map_err(|error| ExpansionError { index, ..error })?;
+            }
+            f(&output);
+            index = index.saturating_add(1);
+            // Advance the last part that isn't on its last value, and reset
+            // every part after it.
+            changed = parts.len();
+            loop {
+                if changed == 0 {
+                    return Ok(());
+                }
+                changed -= 1;
+                digits[changed] += 1;
+                if in_bounds(digits[changed], self.children[changed].count) {
+                    break;
+                }
+                digits[changed] = 0;
+            }
+        }
+    }
+}
+
+/// Add `digit` at the given place value to an index, where `None` stands for
+/// anything that overflows a `u128`.
+fn add_digit(index: Option<u128>, digit: Option<u128>, place: Option<u128>) -> Option<u128> {
+    match digit? {
+        0 => index,
+        digit => index?.checked_add(place?.checked_mul(digit)?),
+    }
+}
+
+/// Every way that a prefix of `input` can be produced by a sequence, as the
+/// length of the prefix and the index of the value.
+fn sequence_prefixes(sequence: &Sequence, input: &str) -> Vec<(usize, u128)> {
+    match sequence {
+        Sequence::Int { width, sequence } => number_prefixes(input)
+            .filter_map(|len| {
+                let number: i64 = input[..len].parse().ok()?;
+                // Only the exact formatting of the number may match.
+                let formatted = match *width {
+                    Some(width) => format!("{number:0width$}"),
+                    None => number.to_string(),
+                };
+                if formatted != input[..len] {
+                    return None;
+                }
+                Some((len, sequence.position(number)?))
+            })
+            .collect(),
+        Sequence::Char { sequence, .. } => input
+            .chars()
+            .next()
+            .and_then(|c| Some((c.len_utf8(), sequence.position(c)?)))
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// The lengths of every prefix of `input` that an expression can produce.
+/// Unlike [`Shape::prefixes`], this doesn't track which outputs produce each
+/// prefix, so there are never more states than positions in the input.
+pub(crate) fn prefix_lengths(expression: &Expression<'_>, input: &str) -> Vec<usize> {
+    if expression.parts().is_empty() {
+        return Vec::new();
+    }
+    let mut states = vec![0];
+    for part in expression.parts() {
+        let mut next = Vec::new();
+        for len in states {
+            next.extend(
+                part_prefix_lengths(part, &input[len..])
+                    .into_iter()
+                    .map(|part_len| len + part_len),
+            );
+        }
+        next.sort_unstable();
+        next.dedup();
+        states = next;
+    }
+    states
+}
+
+fn part_prefix_lengths(part: &Part<'_>, input: &str) -> Vec<usize> {
+    match part {
+        Part::Plain(plain) => {
+            if input.starts_with(plain.as_ref()) {
+                vec![plain.len()]
+            } else {
+                Vec::new()
+            }
+        }
+        Part::Sequence(sequence) => sequence_prefixes(sequence, input)
+            .into_iter()
+            .map(|(len, _)| len)
+            .collect(),
+        Part::List(list) => list
+            .items()
+            .iter()
+            .flat_map(|item| part_prefix_lengths(item, input))
+            .collect(),
+        Part::Expression(expression) => prefix_lengths(expression, input),
+    }
+}
+
+/// The lengths of every prefix of `input` that could be a number, which is an
+/// optional minus sign followed by at least one digit.
+fn number_prefixes(input: &str) -> impl Iterator<Item = usize> + '_ {
+    let sign = usize::from(input.starts_with('-'));
+    let digits = input[sign..].bytes().take_while(u8::is_ascii_digit).count();
+    (1..=digits).map(move |digits| sign + digits)
+}
+
+/// Keep only the `limit` smallest indices for each prefix length, where an
+/// index of `None` is larger than any other.
+///
+/// An index is a sum of digits times their place values, and the digits still
+/// to come depend only on the length of the prefix, so the smallest outputs
+/// can only come from the smallest indices so far. This keeps the number of
+/// states from growing with every ambiguous part.
+fn smallest(mut prefixes: Vec<(usize, Option<u128>)>, limit: usize) -> Vec<(usize, Option<u128>)> {
+    prefixes.sort_unstable_by_key(|&(len, index)| (len, index.is_none(), index));
+    prefixes.dedup();
+    let mut kept = Vec::with_capacity(prefixes.len());
+    let mut run = 0;
+    for (i, &(len, index)) in prefixes.iter().enumerate() {
+        run = match i.checked_sub(1) {
+            Some(previous) if prefixes[previous].0 == len => run + 1,
+            _ => 0,
+        };
+        if run < limit {
+            kept.push((len, index));
+        }
+    }
+    kept
+}
+
+impl Shape {
+    /// The ways that a prefix of `input` can be produced by the expression
+    /// this is the shape of, as the length of the prefix and the index of the
+    /// output, where an index of `None` overflows a `u128`. Only the `limit`
+    /// smallest indices are kept for each length.
+    pub(crate) fn prefixes(
+        &self,
+        expression: &Expression<'_>,
+        input: &str,
+        limit: usize,
+    ) -> Vec<(usize, Option<u128>)> {
+        if self.count == Some(0) {
+            return Vec::new();
+        }
+        let mut states = vec![(0, Some(0))];
+        let parts = expression.parts().iter().zip(&self.children);
+        for ((part, shape), place) in parts.zip(&self.places) {
+            let mut next = Vec::new();
+            for (len, index) in states {
+                next.extend(
+                    shape
+                        .part_prefixes(part, &input[len..], limit)
+                        .into_iter()
+                        .map(|(part_len, digit)| (len + part_len, add_digit(index, digit, *place))),
+                );
+            }
+            states = smallest(next, limit);
+        }
+        states
+    }
+
+    fn part_prefixes(
+        &self,
+        part: &Part<'_>,
+        input: &str,
+        limit: usize,
+    ) -> Vec<(usize, Option<u128>)> {
+        match part {
+            Part::Plain(plain) => {
+                if input.starts_with(plain.as_ref()) {
+                    vec![(plain.len(), Some(0))]
+                } else {
+                    Vec::new()
+                }
+            }
+            Part::Sequence(sequence) => sequence_prefixes(sequence, input)
+                .into_iter()
+                .map(|(len, index)| (len, Some(index)))
+                .collect(),
+            Part::List(list) => {
+                let mut prefixes = Vec::new();
+                let items = list.items().iter().zip(&self.children);
+                for ((item, shape), start) in items.zip(&self.starts) {
+                    prefixes.extend(shape.part_prefixes(item, input, limit).into_iter().map(
+                        |(len, index)| {
+                            let index = start
+                                .zip(index)
+                                .and_then(|(start, index)| start.checked_add(index));
+                            (len, index)
+                        },
+                    ));
+                }
+                smallest(prefixes, limit)
+            }
+            Part::Expression(expression) => self.prefixes(expression, input, limit),
+        }
+    }
+}
+
+#[cfg(feature = "rand")]
+impl Shape {
+    /// Decode a uniformly random output of the expression this is the shape
+    /// of, returning its index if that fits in a `u128`.
+    pub(crate) fn random<'s, 'a, 'o, F, R>(
+        &self,
+        expression: &'s Expression<'a>,
+        rng: &mut R,
+        plain: &F,
+    ) -> (Option<u128>, Result<Cow<'o, str>, ExpansionError>)
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+        R: rand::Rng + ?Sized,
+    {
+        match self.count {
+            Some(count) => {
+                let n = rng.gen_range(0..count);
+                let output = self.get(expression, n, false, plain);
+                (Some(n), output.expect("index out of bounds"))
+            }
+            None => {
+                let mut output = Cow::Borrowed("");
+                let result = self
+                    .random_expression(expression, rng, plain, expression.dialect(), &mut output)
+                    .map(|()| output)
+                    .map_err(|error| ExpansionError {
+                        index: u128::MAX,
+                        ..error
+                    });
+                (None, result)
+            }
+        }
+    }
+
+    /// As the outputs of an expression are the product of its parts, a
+    /// uniformly random output is made of a uniformly random value of each
+    /// part.  This only matters when the expression is too large to pick an
+    /// index uniformly.
+    fn random_expression<'s, 'a, 'o, F, R>(
+        &self,
+        expression: &'s Expression<'a>,
+        rng: &mut R,
+        plain: &F,
+        dialect: &Dialect,
+        output: &mut Cow<'o, str>,
+    ) -> Result<(), ExpansionError>
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+        R: rand::Rng + ?Sized,
+    {
+        for (part, shape) in expression.parts().iter().zip(&self.children) {
+            shape.random_part(part, rng, plain, dialect, output)?;
+        }
+        Ok(())
+    }
+
+    fn random_part<'s, 'a, 'o, F, R>(
+        &self,
+        part: &'s Part<'a>,
+        rng: &mut R,
+        plain: &F,
+        dialect: &Dialect,
+        output: &mut Cow<'o, str>,
+    ) -> Result<(), ExpansionError>
+    where
+        F: Fn(&'s Cow<'a, str>) -> Cow<'o, str>,
+        R: rand::Rng + ?Sized,
+    {
+        if let Some(count) = self.count {
+            let n = rng.gen_range(0..count);
+            return self.decode_part(part, n, false, plain, dialect, output);
+        }
+        match part {
+            Part::Expression(expression) => {
+                self.random_expression(expression, rng, plain, dialect, output)
+            }
+            Part::List(list) => {
+                // The item counts don't all fit, so they are weighed
+                // approximately instead.
+                let weight =
+                    |shape: &Shape| shape.count.map_or(2f64.powi(128), |count| count as f64);
+                let total: f64 = self.children.iter().map(weight).sum();
+                let mut choice = rng.gen_range(0.0..total);
+                // Rounding may leave a sliver past the last item.
+                let index = self
+                    .children
+                    .iter()
+                    .position(|shape| {
+                        choice -= weight(shape);
+                        choice < 0.0
+                    })
+                    .unwrap_or(self.children.len() - 1);
+                self.children[index].random_part(&list.items()[index], rng, plain, dialect, output)
+            }
+            Part::Plain(_) | Part::Sequence(_) => {
+                unreachable!("only nodes with children can overflow")
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use std::borrow::Cow;
+
+use crate::shape::Shape;
+use crate::{ExpansionError, Expression};
+
+/// The number of Feistel rounds, which is plenty to scatter neighboring
+/// indices.
+const ROUNDS: u64 = 8;
+
+/// The splitmix64 finalizer, used as the Feistel round function.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A seeded bijection of `0..count`, made from a Feistel network over the
+/// smallest even power of two that covers `count`.  Values that land outside
+/// of `0..count` are fed back through the network until they land inside it,
+/// which takes fewer than four tries on average.
+#[derive(Clone, Debug)]
+struct Permutation {
+    /// The bits in each half of the Feistel network.
+    half: u32,
+    keys: [u64; ROUNDS as usize],
+    /// The size of the permuted range, or `None` for all of `u128`.
+    count: Option<u128>,
+}
+
+impl Permutation {
+    fn new(count: Option<u128>, seed: u64) -> Self {
+        let bits = count.map_or(128, |count| 128 - count.saturating_sub(1).leading_zeros());
+        let mut keys = [0; ROUNDS as usize];
+        for (round, key) in (0..ROUNDS).zip(&mut keys) {
+            *key = mix(seed.wrapping_add(round.wrapping_mul(0x9e37_79b9_7f4a_7c15)));
+        }
+        Permutation {
+            half: bits.div_ceil(2).max(1),
+            keys,
+            count,
+        }
+    }
+
+    fn feistel(&self, value: u128) -> u128 {
+        let mask = u64::MAX >> (64 - self.half);
+        let mut left = (value >> self.half) as u64;
+        let mut right = value as u64 & mask;
+        for key in self.keys {
+            (left, right) = (right, left ^ (mix(right ^ key) & mask));
+        }
+        (u128::from(left) << self.half) | u128::from(right)
+    }
+
+    fn get(&self, index: u128) -> u128 {
+        let mut value = self.feistel(index);
+        while self.count.is_some_and(|count| value >= count) {
+            value = self.feistel(value);
+        }
+        value
+    }
+}
+
+/// Iterates over every output of an [`Expression`] exactly once, in a
+/// pseudo-random order, created by [`Expression::shuffled`].
+///
+/// The order is a seeded permutation of the output indices, and each output
+/// is decoded directly from its index, so this uses constant memory however
+/// many outputs there are.
+///
+/// As with [`ExpressionIterator`](crate::ExpressionIterator), the size hint is
+/// exact whenever the remaining count fits in a `usize`, and
+/// [`ExactSizeIterator`] is deliberately not implemented, as the count may
+/// not.  [`remaining`](Self::remaining) has the exact count.
+#[derive(Clone, Debug)]
+pub struct Shuffled<'e, 'a> {
+    expression: &'e Expression<'a>,
+    shape: Shape,
+    permutation: Permutation,
+    /// The position in the permuted order of the next output.
+    next: u128,
+}
+
+impl<'e, 'a> Shuffled<'e, 'a> {
+    pub(crate) fn new(expression: &'e Expression<'a>, seed: u64) -> Self {
+        let shape = Shape::of_expression(expression);
+        Shuffled {
+            permutation: Permutation::new(shape.count(), seed),
+            expression,
+            shape,
+            next: 0,
+        }
+    }
+
+    /// The number of outputs left, or `None` if that overflows a `u128`.
+    pub fn remaining(&self) -> Option<u128> {
+        self.shape.count().map(|count| count - self.next)
+    }
+}
+
+impl<'e> Iterator for Shuffled<'e, '_> {
+    type Item = Result<Cow<'e, str>, ExpansionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining() == Some(0) {
+            return None;
+        }
+        let index = self.permutation.get(self.next);
+        self.next += 1;
+        self.shape.get(self.expression, index, false, &|plain| {
+            Cow::Borrowed(plain.as_ref())
+        })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining().map(usize::try_from) {
+            Some(Ok(remaining)) => (remaining, Some(remaining)),
+            _ => (usize::MAX, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permutation() {
+        for count in [1, 2, 3, 7, 64, 100, 1000] {
+            let permutation = Permutation::new(Some(count), 42);
+            let mut seen = vec![false; count as usize];
+            for index in 0..count {
+                let value = permutation.get(index);
+                assert!(!seen[value as usize], "{count}: {value} repeated");
+                seen[value as usize] = true;
+            }
+        }
+        let permutation = Permutation::new(None, 42);
+        assert_ne!(permutation.get(0), permutation.get(1));
+    }
+}
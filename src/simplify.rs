@@ -0,0 +1,453 @@
+//! Rewriting expressions into simpler ones with the same outputs.
+//!
+//! Simplifying keeps the outputs in the same order, so it only flattens what
+//! is nested without need and turns progressions of list items into
+//! sequences.  Canonicalizing may also reorder list items, so that any two
+//! lists of the same values end up the same.
+
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::sync::Arc;
+
+use crate::compress::MIN_RUN;
+use crate::{sequence, Dialect, Expression, List, Part, Sequence};
+
+pub(crate) fn expression<'a>(expression: &Expression<'a>, canonical: bool) -> Expression<'a> {
+    let dialect = &expression.dialect;
+    let mut parts = Vec::new();
+    for part in expression.parts() {
+        push(&mut parts, self::part(part, dialect, canonical));
+    }
+    if parts.is_empty() && expression.count() != Some(0) {
+        // Only the empty string is left, which needs an empty list, as an
+        // expression without parts has no outputs at all.
+        parts.push(Part::List(List(vec![Part::Plain(Cow::Borrowed(""))])));
+    }
+    Expression {
+        parts,
+        dialect: dialect.clone(),
+    }
+}
+
+/// Append a simplified part, merging it into its neighbours where possible.
+fn push<'a>(parts: &mut Vec<Part<'a>>, part: Part<'a>) {
+    match part {
+        Part::Plain(plain) if plain.is_empty() => {}
+        Part::Plain(plain) => match parts.last_mut() {
+            Some(Part::Plain(last)) => last.to_mut().push_str(&plain),
+            _ => parts.push(Part::Plain(plain)),
+        },
+        // A nested expression is just its parts, unless it has none, in which
+        // case it has no outputs.
+        Part::Expression(expression) if !expression.parts.is_empty() => {
+            for part in expression.parts {
+                push(parts, part);
+            }
+        }
+        // A list of one item is just that item.
+        Part::List(List(mut items)) if items.len() == 1 => {
+            push(parts, items.pop().expect("the list has an item"));
+        }
+        part => parts.push(part),
+    }
+}
+
+fn part<'a>(part: &Part<'a>, dialect: &Option<Arc<Dialect>>, canonical: bool) -> Part<'a> {
+    match part {
+        Part::Plain(_) => part.clone(),
+        Part::List(list) => Part::List(self::list(list.items(), dialect, canonical)),
+        // A canonical sequence is whatever a list of its values would be.
+        Part::Sequence(_) if canonical => {
+            Part::List(self::list(std::slice::from_ref(part), dialect, canonical))
+        }
+        Part::Sequence(sequence) => match Atom::of(part) {
+            Some(atom) if sequence.count() == 1 => match atom.value(0) {
+                Some(value) => Part::Plain(Cow::Owned(value)),
+                None => part.clone(),
+            },
+            _ => part.clone(),
+        },
+        Part::Expression(expression) => Part::Expression(self::expression(expression, canonical)),
+    }
+}
+
+/// A list item made of a single part.
+fn item<'a>(part: Part<'a>, dialect: &Option<Arc<Dialect>>) -> Part<'a> {
+    match part {
+        Part::Plain(plain) if plain.is_empty() => Part::Plain(plain),
+        Part::Expression(expression) => Part::Expression(expression),
+        part => Part::Expression(Expression {
+            parts: vec![part],
+            dialect: dialect.clone(),
+        }),
+    }
+}
+
+fn list<'a>(items: &[Part<'a>], dialect: &Option<Arc<Dialect>>, canonical: bool) -> List<'a> {
+    // Simplify each item, splicing in the items of any item that is a list
+    // itself.
+    let mut flat = Vec::new();
+    for item in items {
+        // A canonical progression is written from its values, so it doesn't
+        // need simplifying first.
+        if canonical && Atom::of(item).is_some() {
+            flat.push(self::item(item.clone(), dialect));
+            continue;
+        }
+        let simplified = match item {
+            Part::Expression(expression) => self::expression(expression, canonical),
+            item => self::expression(
+                &Expression {
+                    parts: vec![item.clone()],
+                    dialect: dialect.clone(),
+                },
+                canonical,
+            ),
+        };
+        match <[_; 1]>::try_from(simplified.parts) {
+            Ok([Part::List(List(items))]) => flat.extend(items),
+            Ok([part]) => flat.push(self::item(part, dialect)),
+            Err(parts) => flat.push(Part::Expression(Expression {
+                parts,
+                dialect: simplified.dialect,
+            })),
+        }
+    }
+
+    // Merge items that are values of a progression into sequences.
+    let mut atoms: Vec<Atom<'a>> = Vec::new();
+    let mut others = Vec::new();
+    let mut merged = Vec::new();
+    for item in flat {
+        let Some(mut atom) = Atom::of(&item) else {
+            if canonical {
+                others.push(item);
+            } else {
+                merged.extend(atoms.drain(..).flat_map(|atom| atom.items(false, dialect)));
+                merged.push(item);
+            }
+            continue;
+        };
+        atom.items.push(item);
+        if canonical {
+            atom.ascend();
+            atoms.extend(atom.split(dialect));
+            continue;
+        }
+        match atoms.last_mut() {
+            Some(last) => match last.merge(&atom) {
+                Some(mut next) => {
+                    next.items = std::mem::take(&mut last.items);
+                    next.items.extend(atom.items);
+                    *last = next;
+                }
+                None => atoms.push(atom),
+            },
+            None => atoms.push(atom),
+        }
+    }
+    if canonical {
+        // Merge each value into any progression it continues, in order of
+        // value, preferring to continue progressions of more than one value.
+        atoms.sort_by_key(|atom| (atom.kind == Kind::Char, atom.start, Reverse(atom.count)));
+        let mut progressions: Vec<Atom<'a>> = Vec::new();
+        for atom in atoms {
+            let (singles, longer): (Vec<_>, Vec<_>) = progressions
+                .iter_mut()
+                .rev()
+                .partition(|progression| progression.count == 1);
+            let progression = longer
+                .into_iter()
+                .chain(singles)
+                .find_map(|progression| Some((progression.merge(&atom)?, progression)));
+            match progression {
+                Some((mut next, progression)) => {
+                    next.items = std::mem::take(&mut progression.items);
+                    next.items.extend(atom.items);
+                    *progression = next;
+                }
+                None => progressions.push(atom),
+            }
+        }
+        merged.extend(
+            progressions
+                .into_iter()
+                .flat_map(|atom| atom.items(true, dialect)),
+        );
+        merged.extend(others);
+        merged.sort_by_cached_key(ToString::to_string);
+    } else {
+        merged.extend(
+            atoms
+                .into_iter()
+                .flat_map(|atom| atom.items(false, dialect)),
+        );
+    }
+    List(merged)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Numbers, zero-padded to a width if any.
+    Int(Option<usize>),
+    Char,
+}
+
+/// A list item whose values are a progression of numbers or chars.
+#[derive(Clone, Debug)]
+struct Atom<'a> {
+    kind: Kind,
+    start: i128,
+    /// The difference between consecutive values, which is zero if there is
+    /// only one value.
+    step: i128,
+    count: u128,
+    /// The items the progression was made from.
+    items: Vec<Part<'a>>,
+}
+
+/// The length of a number written without padding.
+fn natural(number: i128) -> usize {
+    number.to_string().len()
+}
+
+impl<'a> Atom<'a> {
+    /// The progression an item produces, if it produces one, without the item
+    /// itself.
+    fn of(item: &Part<'_>) -> Option<Self> {
+        let part = match item {
+            Part::Expression(expression) if expression.parts.len() == 1 => &expression.parts[0],
+            part => part,
+        };
+        let (kind, start, step, count) = match part {
+            Part::Plain(plain) => {
+                if let Ok(number) = plain.parse::<i64>() {
+                    let len = plain.len();
+                    let kind = if *plain == number.to_string() {
+                        Kind::Int(None)
+                    } else if *plain == format!("{number:0len$}") {
+                        Kind::Int(Some(len))
+                    } else {
+                        return None;
+                    };
+                    (kind, i128::from(number), 0, 1)
+                } else {
+                    let mut chars = plain.chars();
+                    match (chars.next(), chars.next()) {
+                        (Some(c), None) => (Kind::Char, i128::from(u32::from(c)), 0, 1),
+                        _ => return None,
+                    }
+                }
+            }
+            Part::Sequence(Sequence::Int { width, sequence }) => {
+                let (start, end) = (i128::from(sequence.start), i128::from(sequence.end));
+                let step = i128::from(sequence.incr.max(1));
+                let step = if start <= end { step } else { -step };
+                let nearest = 0.clamp(start.min(end), start.max(end));
+                // A width that doesn't pad any number makes no difference.
+                let width = width.filter(|&width| width > natural(nearest));
+                (Kind::Int(width), start, step, sequence.count())
+            }
+            Part::Sequence(Sequence::Char { sequence, .. }) => {
+                let (start, end) = (u32::from(sequence.start), u32::from(sequence.end));
+                let step = i128::from(sequence.incr.max(1));
+                let step = if start <= end { step } else { -step };
+                (Kind::Char, i128::from(start), step, sequence.count())
+            }
+            _ => return None,
+        };
+        Some(Atom {
+            kind,
+            start,
+            step: if count == 1 { 0 } else { step },
+            count,
+            items: Vec::new(),
+        })
+    }
+
+    fn last(&self) -> i128 {
+        self.start + (self.count - 1) as i128 * self.step
+    }
+
+    /// Value `n` of the progression, unless it is an invalid char.
+    fn value(&self, n: u128) -> Option<String> {
+        let value = self.start + n as i128 * self.step;
+        match self.kind {
+            Kind::Int(Some(width)) => Some(format!("{value:0width$}")),
+            Kind::Int(None) => Some(value.to_string()),
+            Kind::Char => char::from_u32(u32::try_from(value).ok()?).map(String::from),
+        }
+    }
+
+    /// The sequence producing the progression, if one can be written.
+    fn sequence(&self) -> Option<Sequence> {
+        let incr = self.step.unsigned_abs().max(1);
+        match self.kind {
+            Kind::Int(width) => {
+                let (start, end) = (
+                    i64::try_from(self.start).ok()?,
+                    i64::try_from(self.last()).ok()?,
+                );
+                // The width is written as that of the wider endpoint, so it
+                // can't be narrower than either.
+                if width.is_some_and(|width| natural(self.start).max(natural(self.last())) > width)
+                {
+                    return None;
+                }
+                let nearest = 0.clamp(self.start.min(self.last()), self.start.max(self.last()));
+                Some(Sequence::Int {
+                    width: width.filter(|&width| width > natural(nearest)),
+                    sequence: sequence::Sequence {
+                        start,
+                        end,
+                        incr: u64::try_from(incr).ok()?,
+                    },
+                })
+            }
+            Kind::Char => Some(Sequence::Char {
+                sequence: sequence::Sequence {
+                    start: char::from_u32(u32::try_from(self.start).ok()?)?,
+                    end: char::from_u32(u32::try_from(self.last()).ok()?)?,
+                    incr: u32::try_from(incr).ok()?,
+                },
+                span: None,
+            }),
+        }
+    }
+
+    /// Reverse a descending progression, if its ends can still be written.
+    fn ascend(&mut self) {
+        if self.step < 0 {
+            let ascending = Atom {
+                start: self.last(),
+                step: -self.step,
+                items: Vec::new(),
+                ..*self
+            };
+            if ascending.sequence().is_some() {
+                self.start = ascending.start;
+                self.step = ascending.step;
+            }
+        }
+    }
+
+    /// Split a short progression into its values, so that each of them can be
+    /// merged into another progression.
+    fn split(self, dialect: &Option<Arc<Dialect>>) -> Vec<Atom<'a>> {
+        if self.count >= MIN_RUN as u128 {
+            return vec![self];
+        }
+        match (0..self.count)
+            .map(|n| self.value(n))
+            .collect::<Option<Vec<_>>>()
+        {
+            Some(values) => values
+                .into_iter()
+                .filter_map(|value| {
+                    let item = item(Part::Plain(Cow::Owned(value)), dialect);
+                    let mut atom = Atom::of(&item)?;
+                    atom.items.push(item);
+                    Some(atom)
+                })
+                .collect(),
+            None => vec![self],
+        }
+    }
+
+    /// The progression of this one followed by `next`, if it is one, without
+    /// any items.
+    fn merge(&self, next: &Atom<'_>) -> Option<Atom<'a>> {
+        let kind = match (self.kind, next.kind) {
+            (a, b) if a == b => a,
+            // Numbers that are never shorter than a width are the same padded
+            // to it.
+            (Kind::Int(Some(width)), Kind::Int(None)) if next.shortest() >= width => self.kind,
+            (Kind::Int(None), Kind::Int(Some(width))) if self.shortest() >= width => next.kind,
+            _ => return None,
+        };
+        let step = match (self.count, next.count) {
+            (1, 1) => next.start - self.start,
+            (1, _) => next.step,
+            (_, 1) => self.step,
+            _ if self.step == next.step => self.step,
+            _ => return None,
+        };
+        if step == 0 || self.last().checked_add(step)? != next.start {
+            return None;
+        }
+        let merged = Atom {
+            kind,
+            start: self.start,
+            step,
+            count: self.count.checked_add(next.count)?,
+            items: Vec::new(),
+        };
+        merged.sequence().map(|_| merged)
+    }
+
+    /// The length of the shortest number, without padding.
+    fn shortest(&self) -> usize {
+        natural(0.clamp(self.start.min(self.last()), self.start.max(self.last())))
+    }
+
+    /// The list items producing the progression.  Short progressions are
+    /// written as their values if canonical, and otherwise as the items they
+    /// were made from.
+    fn items(self, canonical: bool, dialect: &Option<Arc<Dialect>>) -> Vec<Part<'a>> {
+        if self.count < MIN_RUN as u128 {
+            if !canonical {
+                return self.items;
+            }
+            if let Some(values) = (0..self.count)
+                .map(|n| self.value(n))
+                .collect::<Option<Vec<_>>>()
+            {
+                return values
+                    .into_iter()
+                    .map(|value| item(Part::Plain(Cow::Owned(value)), dialect))
+                    .collect();
+            }
+        } else if !canonical && self.items.len() == 1 {
+            return self.items;
+        }
+        match self.sequence() {
+            Some(sequence) => vec![item(Part::Sequence(sequence), dialect)],
+            None => self.items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge() {
+        let atom = |input: &str| {
+            let expression = Expression::try_from(input).unwrap();
+            Atom::of(&expression.parts()[0]).unwrap()
+        };
+        let test_cases = [
+            ("1", "2", Some("{1..2}")),
+            ("{1..5..2}", "7", Some("{1..7..2}")),
+            ("{1..5..2}", "6", None),
+            ("{5..1}", "{0..-3}", Some("{5..-3}")),
+            ("09", "{10..12}", Some("{=9..12}")),
+            ("08", "{9..12}", None),
+            ("{=1..5}", "06", None),
+            ("a", "c", Some("{a..c..2}")),
+            ("a", "1", None),
+        ];
+        for (first, next, expected) in test_cases {
+            let merged = atom(first).merge(&atom(next));
+            assert_eq!(
+                merged
+                    .and_then(|merged| merged.sequence())
+                    .map(|sequence| sequence.to_string())
+                    .as_deref(),
+                expected,
+                "{first} {next}"
+            );
+        }
+    }
+}
@@ -0,0 +1,274 @@
+//! Deduplication of the outputs of an expression.
+//!
+//! Most expressions can't produce the same output twice, and the ones that
+//! can usually only do so in a few of their parts.  Each part is classified as
+//! a code, in the information theory sense: whether its values are all
+//! distinct, and whether any value is a prefix or suffix of another.  A part
+//! whose values are distinct and prefix-free is a prefix code, and the value
+//! of a prefix code can always be told apart from whatever follows it.
+//!
+//! So when the leading parts of an expression are all prefix codes, two equal
+//! outputs must have the same values for those parts.  They are the most
+//! significant digits of the output index, so equal outputs are always in the
+//! same run of outputs, and only the outputs of the current run need to be
+//! remembered.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use crate::{ExpansionError, Expression, ExpressionIterator, Part, Sequence};
+
+/// Parts with at most this many values are classified exactly, by producing
+/// all of their values.
+const EXACT_LIMIT: u128 = 1024;
+
+/// What is known about the values of a part.  Each property is only set when
+/// it's certain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Code {
+    /// No two values are the same.
+    distinct: bool,
+    /// No value is a proper prefix of another.
+    prefix_free: bool,
+    /// No value is a proper suffix of another.
+    suffix_free: bool,
+}
+
+impl Code {
+    const UNKNOWN: Code = Code {
+        distinct: false,
+        prefix_free: false,
+        suffix_free: false,
+    };
+
+    const SINGLE: Code = Code {
+        distinct: true,
+        prefix_free: true,
+        suffix_free: true,
+    };
+
+    fn prefix_code(self) -> bool {
+        self.distinct && self.prefix_free
+    }
+
+    fn suffix_code(self) -> bool {
+        self.distinct && self.suffix_free
+    }
+
+    /// Classify a set of values exactly.
+    fn of_values(mut values: Vec<String>) -> Self {
+        values.sort_unstable();
+        let distinct = values.windows(2).all(|pair| pair[0] != pair[1]);
+        // In sorted order, a value that is a prefix of another is also a
+        // prefix of the value right after it.
+        let prefix_free = values.windows(2).all(|pair| !pair[1].starts_with(&pair[0]));
+        let mut reversed: Vec<String> = values
+            .into_iter()
+            .map(|value| value.chars().rev().collect())
+            .collect();
+        reversed.sort_unstable();
+        let suffix_free = reversed
+            .windows(2)
+            .all(|pair| !pair[1].starts_with(&pair[0]));
+        Code {
+            distinct,
+            prefix_free: distinct && prefix_free,
+            suffix_free: distinct && suffix_free,
+        }
+    }
+
+    fn of_expression(expression: &Expression<'_>) -> Self {
+        match expression.count() {
+            Some(count) if count <= EXACT_LIMIT => Code::of_values(
+                expression
+                    .iter()
+                    .filter_map(Result::ok)
+                    .map(Cow::into_owned)
+                    .collect(),
+            ),
+            _ => Code::of_parts(&Code::of_parts_each(expression)),
+        }
+    }
+
+    fn of_parts_each(expression: &Expression<'_>) -> Vec<Code> {
+        expression.parts().iter().map(Code::of_part).collect()
+    }
+
+    /// Classify the concatenation of parts from the classes of each part.
+    fn of_parts(codes: &[Code]) -> Self {
+        // The output is distinct if every part before some part is a prefix
+        // code and every part after it is a suffix code, as the values on
+        // either side can be peeled off one at a time.
+        let prefix_codes = codes.iter().take_while(|code| code.prefix_code()).count();
+        let suffix_codes = codes
+            .iter()
+            .rev()
+            .take_while(|code| code.suffix_code())
+            .count();
+        let distinct = prefix_codes + suffix_codes + 1 >= codes.len()
+            && codes.get(prefix_codes).is_none_or(|middle| middle.distinct);
+        Code {
+            distinct,
+            prefix_free: prefix_codes == codes.len(),
+            suffix_free: suffix_codes == codes.len(),
+        }
+    }
+
+    fn of_part(part: &Part<'_>) -> Self {
+        match part {
+            Part::Plain(_) => Code::SINGLE,
+            // Each value is a single, different char.
+            Part::Sequence(Sequence::Char { .. }) => Code::SINGLE,
+            Part::Sequence(Sequence::Int { width, sequence }) => {
+                let len = |number: i64| match *width {
+                    Some(width) => format!("{number:0width$}").len(),
+                    None => number.to_string().len(),
+                };
+                let (low, high) = (
+                    sequence.start.min(sequence.end),
+                    sequence.start.max(sequence.end),
+                );
+                // The shortest number is the one closest to zero.
+                let shortest = len(low.max(0).min(high));
+                let longest = len(low).max(len(high));
+                // Numbers of the same length can't be prefixes of each other.
+                let fixed = shortest == longest;
+                Code {
+                    distinct: true,
+                    prefix_free: fixed,
+                    suffix_free: fixed,
+                }
+            }
+            Part::List(list) => match part.count() {
+                Some(count) if count <= EXACT_LIMIT => Code::of_expression(&Expression {
+                    parts: vec![part.clone()],
+                    dialect: None,
+                }),
+                _ => match list.items() {
+                    [item] => Code::of_part(item),
+                    _ => Code::UNKNOWN,
+                },
+            },
+            Part::Expression(expression) => Code::of_expression(expression),
+        }
+    }
+}
+
+/// Iterates over the outputs of an [`Expression`], skipping any output that
+/// was already produced, created by [`ExpressionIterator::unique`].
+///
+/// Outputs stay in the order they were first produced.  Only outputs that can
+/// actually be repeated are remembered, so an expression that can't produce
+/// duplicates at all is iterated without remembering anything.  Errors are
+/// never considered duplicates.
+#[derive(Clone, Debug)]
+pub struct Unique<'a> {
+    iter: ExpressionIterator<'a>,
+    /// The outputs of the current run, or `None` if there are no duplicates.
+    seen: Option<HashSet<String>>,
+    /// The number of outputs in each run that duplicates are confined to, or
+    /// `None` if that's all of them.
+    run: Option<u128>,
+    /// The run of the previous output.
+    current: u128,
+}
+
+impl<'a> Unique<'a> {
+    pub(crate) fn new(iter: ExpressionIterator<'a>) -> Self {
+        let codes = Code::of_parts_each(&iter.expression);
+        let distinct = match iter.shape.count() {
+            Some(count) if count <= EXACT_LIMIT => Code::of_expression(&iter.expression).distinct,
+            _ => Code::of_parts(&codes).distinct,
+        };
+        let prefix_codes = codes.iter().take_while(|code| code.prefix_code()).count();
+        let run = prefix_codes
+            .checked_sub(1)
+            .and_then(|last| iter.shape.place(last));
+        Unique {
+            seen: (!distinct).then(HashSet::new),
+            run,
+            current: 0,
+            iter,
+        }
+    }
+}
+
+impl<'a> Iterator for Unique<'a> {
+    type Item = Result<Cow<'a, str>, ExpansionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Some(seen) = &mut self.seen else {
+            return self.iter.next();
+        };
+        loop {
+            let run = self.run.map_or(0, |run| self.iter.front / run);
+            let output = self.iter.next()?;
+            if run != self.current {
+                seen.clear();
+                self.current = run;
+            }
+            match output {
+                Ok(output) if !seen.insert(output.clone().into_owned()) => continue,
+                output => return Some(output),
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.seen {
+            Some(_) => (0, self.iter.size_hint().1),
+            None => self.iter.size_hint(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unique(input: &str) -> Unique<'_> {
+        Expression::try_from(input).unwrap().into_iter().unique()
+    }
+
+    #[test]
+    fn test_code() {
+        let test_cases = [
+            ("{a..z}", Code::SINGLE),
+            ("{=1..999}", Code::SINGLE),
+            ("{=-10..10}", Code::SINGLE),
+            (
+                "{1..1000000}",
+                Code {
+                    distinct: true,
+                    prefix_free: false,
+                    suffix_free: false,
+                },
+            ),
+            (
+                "{a,ab}",
+                Code {
+                    distinct: true,
+                    prefix_free: false,
+                    suffix_free: true,
+                },
+            ),
+            ("{a,,a}", Code::UNKNOWN),
+        ];
+        for (input, expected) in test_cases {
+            let expression = Expression::try_from(input).unwrap();
+            assert_eq!(Code::of_part(&expression.parts()[0]), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn test_unique_analysis() {
+        // Neither of these can produce duplicates.
+        assert!(unique("host{1..1000000}.{us,eu}").seen.is_none());
+        assert!(unique("{a..z}{=1..1000000}{1..1000000}").seen.is_none());
+        // These can, but only within runs of the leading prefix codes.
+        let iter = unique("{a..z}{=1..1000}{a,ab}{bc,c}");
+        assert!(iter.seen.is_some());
+        assert_eq!(iter.run, Some(4));
+        assert_eq!(unique("{1..1000000}{,1}").run, None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Traversal of the expression syntax tree.
+//!
+//! [`Visitor`] walks an expression by reference, and [`VisitorMut`] walks it
+//! mutably so that it may be rewritten in place.  Every method has a default
+//! implementation that continues the walk with the matching `walk_*` function,
+//! so an implementation only needs to override the nodes it cares about, and
+//! may call the `walk_*` function itself to keep descending.
+//!
+//! ```
+//! use bexpand::{visit::Visitor, Expression, List};
+//!
+//! #[derive(Default)]
+//! struct ListCounter(usize);
+//!
+//! impl Visitor<'_> for ListCounter {
+//!     fn visit_list(&mut self, list: &List<'_>) {
+//!         self.0 += 1;
+//!         bexpand::visit::walk_list(self, list);
+//!     }
+//! }
+//!
+//! let expression: Expression = "a{b,c{d,e}}{1..3}".try_into().unwrap();
+//! let mut counter = ListCounter::default();
+//! counter.visit_expression(&expression);
+//! assert_eq!(counter.0, 2);
+//! ```
+
+use std::borrow::Cow;
+
+use crate::{sequence, Expression, List, Part, Sequence};
+
+/// Walks an expression by reference.
+pub trait Visitor<'e> {
+    /// Called on the expression the walk starts from, and on every nested
+    /// expression, like the items of a list.  By default, visits each of its
+    /// parts.
+    fn visit_expression(&mut self, expression: &'e Expression<'_>) {
+        walk_expression(self, expression);
+    }
+
+    /// Called on every part of an expression and every item of a list.  By
+    /// default, visits whatever the part holds.
+    fn visit_part(&mut self, part: &'e Part<'_>) {
+        walk_part(self, part);
+    }
+
+    /// Called on every plain string, without its escapes.  Plain strings have
+    /// no children, so by default this does nothing.
+    fn visit_plain(&mut self, _plain: &'e str) {}
+
+    /// Called on every list.  By default, visits each of its items.
+    fn visit_list(&mut self, list: &'e List<'_>) {
+        walk_list(self, list);
+    }
+
+    /// Called on every sequence.  By default, visits it as a numeric or a
+    /// character sequence.
+    fn visit_sequence(&mut self, sequence: &'e Sequence) {
+        walk_sequence(self, sequence);
+    }
+
+    /// Called on every numeric sequence, with the width its numbers are
+    /// padded to, if any.  Sequences have no children, so by default this does
+    /// nothing.
+    fn visit_int_sequence(
+        &mut self,
+        _width: Option<usize>,
+        _sequence: &'e sequence::Sequence<i64>,
+    ) {
+    }
+
+    /// Called on every character sequence.  Sequences have no children, so by
+    /// default this does nothing.
+    fn visit_char_sequence(&mut self, _sequence: &'e sequence::Sequence<char>) {}
+}
+
+/// Visit each part of the expression.
+pub fn walk_expression<'e, V>(visitor: &mut V, expression: &'e Expression<'_>)
+where
+    V: Visitor<'e> + ?Sized,
+{
+    for part in expression.parts() {
+        visitor.visit_part(part);
+    }
+}
+
+/// Visit the contents of the part.
+pub fn walk_part<'e, V>(visitor: &mut V, part: &'e Part<'_>)
+where
+    V: Visitor<'e> + ?Sized,
+{
+    match part {
+        Part::Plain(plain) => visitor.visit_plain(plain),
+        Part::List(list) => visitor.visit_list(list),
+        Part::Sequence(sequence) => visitor.visit_sequence(sequence),
+        Part::Expression(expression) => visitor.visit_expression(expression),
+    }
+}
+
+/// Visit each item of the list.
+pub fn walk_list<'e, V>(visitor: &mut V, list: &'e List<'_>)
+where
+    V: Visitor<'e> + ?Sized,
+{
+    for item in list.items() {
+        visitor.visit_part(item);
+    }
+}
+
+/// Visit the numeric or character sequence.
+pub fn walk_sequence<'e, V>(visitor: &mut V, sequence: &'e Sequence)
+where
+    V: Visitor<'e> + ?Sized,
+{
+    match sequence {
+        Sequence::Int { width, sequence } => visitor.visit_int_sequence(*width, sequence),
+        Sequence::Char { sequence, .. } => visitor.visit_char_sequence(sequence),
+    }
+}
+
+/// Walks an expression mutably, so that it may be rewritten in place.
+pub trait VisitorMut {
+    /// Called on the expression the walk starts from, and on every nested
+    /// expression, like the items of a list.  By default, visits each of its
+    /// parts.
+    fn visit_expression_mut(&mut self, expression: &mut Expression<'_>) {
+        walk_expression_mut(self, expression);
+    }
+
+    /// Called on every part of an expression and every item of a list, which
+    /// may be replaced outright.  By default, visits whatever the part holds.
+    fn visit_part_mut(&mut self, part: &mut Part<'_>) {
+        walk_part_mut(self, part);
+    }
+
+    /// Called on every plain string, without its escapes.  Plain strings have
+    /// no children, so by default this does nothing.
+    fn visit_plain_mut(&mut self, _plain: &mut Cow<'_, str>) {}
+
+    /// Called on every list.  By default, visits each of its items.
+    fn visit_list_mut(&mut self, list: &mut List<'_>) {
+        walk_list_mut(self, list);
+    }
+
+    /// Called on every sequence, which may be replaced outright.  By default,
+    /// visits it as a numeric or a character sequence.
+    fn visit_sequence_mut(&mut self, sequence: &mut Sequence) {
+        walk_sequence_mut(self, sequence);
+    }
+
+    /// Called on every numeric sequence, with the width its numbers are
+    /// padded to, if any.  Sequences have no children, so by default this does
+    /// nothing.
+    fn visit_int_sequence_mut(
+        &mut self,
+        _width: &mut Option<usize>,
+        _sequence: &mut sequence::Sequence<i64>,
+    ) {
+    }
+
+    /// Called on every character sequence.  Sequences have no children, so by
+    /// default this does nothing.
+    fn visit_char_sequence_mut(&mut self, _sequence: &mut sequence::Sequence<char>) {}
+}
+
+/// Visit each part of the expression mutably.
+pub fn walk_expression_mut<V>(visitor: &mut V, expression: &mut Expression<'_>)
+where
+    V: VisitorMut + ?Sized,
+{
+    for part in expression.parts_mut() {
+        visitor.visit_part_mut(part);
+    }
+}
+
+/// Visit the contents of the part mutably.
+pub fn walk_part_mut<V>(visitor: &mut V, part: &mut Part<'_>)
+where
+    V: VisitorMut + ?Sized,
+{
+    match part {
+        Part::Plain(plain) => visitor.visit_plain_mut(plain),
+        Part::List(list) => visitor.visit_list_mut(list),
+        Part::Sequence(sequence) => visitor.visit_sequence_mut(sequence),
+        Part::Expression(expression) => visitor.visit_expression_mut(expression),
+    }
+}
+
+/// Visit each item of the list mutably.
+pub fn walk_list_mut<V>(visitor: &mut V, list: &mut List<'_>)
+where
+    V: VisitorMut + ?Sized,
+{
+    for item in list.items_mut() {
+        visitor.visit_part_mut(item);
+    }
+}
+
+/// Visit the numeric or character sequence mutably.
+pub fn walk_sequence_mut<V>(visitor: &mut V, sequence: &mut Sequence)
+where
+    V: VisitorMut + ?Sized,
+{
+    match sequence {
+        Sequence::Int { width, sequence } => visitor.visit_int_sequence_mut(width, sequence),
+        Sequence::Char { sequence, .. } => visitor.visit_char_sequence_mut(sequence),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Collector<'e> {
+        plains: Vec<&'e str>,
+        sequences: usize,
+    }
+
+    impl<'e> Visitor<'e> for Collector<'e> {
+        fn visit_plain(&mut self, plain: &'e str) {
+            self.plains.push(plain);
+        }
+
+        fn visit_int_sequence(&mut self, _: Option<usize>, _: &'e sequence::Sequence<i64>) {
+            self.sequences += 1;
+        }
+
+        fn visit_char_sequence(&mut self, _: &'e sequence::Sequence<char>) {
+            self.sequences += 1;
+        }
+    }
+
+    struct Rewriter;
+
+    impl VisitorMut for Rewriter {
+        fn visit_plain_mut(&mut self, plain: &mut Cow<'_, str>) {
+            *plain = Cow::Owned(plain.to_uppercase());
+        }
+
+        fn visit_int_sequence_mut(
+            &mut self,
+            width: &mut Option<usize>,
+            sequence: &mut sequence::Sequence<i64>,
+        ) {
+            *width = None;
+            sequence.end *= 5;
+        }
+    }
+
+    #[test]
+    fn test_visitor() {
+        let expression: Expression = "a{b,c{d,}e}{1..3}{x..z}".try_into().unwrap();
+        let mut collector = Collector::default();
+        collector.visit_expression(&expression);
+        assert_eq!(collector.plains, ["a", "b", "c", "d", "", "e"]);
+        assert_eq!(collector.sequences, 2);
+    }
+
+    #[test]
+    fn test_visitor_mut() {
+        let mut expression: Expression = "a{b,c}{=1..2}".try_into().unwrap();
+        Rewriter.visit_expression_mut(&mut expression);
+        assert_eq!(expression.to_string(), "A{B,C}{1..10}");
+        let generated: Result<Vec<_>, _> = expression.into_iter().collect();
+        assert_eq!(generated.unwrap()[8..11], ["AB9", "AB10", "AC1"]);
+    }
+}